@@ -1,14 +1,29 @@
-use clap::Parser;
+use std::ffi::OsStr;
 
-use crate::comments;
+use clap::{
+    Arg, Command, Parser, ValueEnum,
+    builder::{PossibleValue, StringValueParser, TypedValueParser},
+};
+
+use crate::{comments, git::GitSelector, project::ProjectConfig};
 
 /// CLI tool to prepend file paths as comments to source code files
-#[derive(Parser, Clone, Debug)]
+///
+/// Library callers can start from `Args::default()`, which matches running without flags.
+#[derive(Parser, Clone, Default, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Directory to process files in
-    #[arg(required = true)]
-    pub dir: String,
+    /// Files and/or directories to process
+    #[arg(
+        required_unless_present_any = ["stdin_path", "print_extensions", "git_staged", "git_changed", "git_tracked", "git_renamed"],
+        value_name = "PATHS"
+    )]
+    pub paths: Vec<String>,
+
+    /// Read file content from stdin and write it with the header to stdout, using PATH as the
+    /// file's path for the header and comment style. Nothing on disk is modified.
+    #[arg(long, value_name = "PATH", conflicts_with = "paths")]
+    pub stdin_path: Option<String>,
 
     /// Base directory for calculating relative paths.
     /// If not provided, searches upwards for a .git directory to use as the base.
@@ -18,30 +33,61 @@ pub struct Args {
 
     /// Keep other existing path comments in the file.
     /// By default, all path comments are removed from the file.
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, default_value_t = false, overrides_with = "no_keep")]
     pub keep: bool,
 
+    /// Turn off `keep` from a .path-comment.toml
+    #[arg(long, overrides_with = "keep")]
+    pub no_keep: bool,
+
     /// If used, the --keep is ignored.
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, overrides_with = "no_clean")]
     pub clean: bool,
 
+    /// Turn off `clean` from a .path-comment.toml
+    #[arg(long, overrides_with = "clean")]
+    pub no_clean: bool,
+
     /// Process folders that would normally be ignored (node_modules, venv, etc.)
-    #[arg(short, long, default_value_t = false)]
+    /// Rules from .gitignore and .pathcommentignore files still apply.
+    #[arg(short, long, default_value_t = false, overrides_with = "no_force")]
     pub force: bool,
 
+    /// Turn off `force` from a .path-comment.toml
+    #[arg(long, overrides_with = "force")]
+    pub no_force: bool,
+
     /// Disable searching for a .git directory to determine the base path.
     /// If --base is not provided, uses the current working directory.
     #[arg(long, default_value_t = false)]
     pub no_git: bool,
 
     /// Disables processing files recursively
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, overrides_with = "recursive")]
     pub no_recursive: bool,
 
-    /// Disable merging ignore rules from .gitignore found in the base directory.
-    #[arg(long, default_value_t = false)]
+    /// Turn off `no-recursive` from a .path-comment.toml
+    #[arg(long, overrides_with = "no_recursive")]
+    pub recursive: bool,
+
+    /// Disable applying ignore rules from .gitignore files (the base directory's and nested ones).
+    #[arg(long, default_value_t = false, overrides_with = "ignore_merge")]
     pub no_ignore_merge: bool,
 
+    /// Turn off `no-ignore-merge` from a .path-comment.toml
+    #[arg(long, overrides_with = "no_ignore_merge")]
+    pub ignore_merge: bool,
+
+    /// Only process files whose path relative to the base directory matches this glob,
+    /// eg `crates/*/src/**`. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files and directories whose path relative to the base directory matches this glob,
+    /// eg `src/**/generated/**`. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
     /// File extensions to process (comma-separated), eg `rs,ts,toml`
     #[arg(short, long)]
     pub extensions: Option<String>,
@@ -54,17 +100,186 @@ pub struct Args {
     #[arg(short, long)]
     pub dry_run: bool,
 
-    /// Force override a specific comment style to use (overrides config file)
-    #[arg(short = 's', long, value_enum)]
-    pub comment_style: Option<comments::Style>,
+    /// Print a unified diff of every change instead of the usual report, eg to pipe into
+    /// `git apply`. Combine with `--dry-run` to leave the files untouched.
+    #[arg(long, conflicts_with = "check")]
+    pub diff: bool,
+
+    /// Output format of the per-file report and summary. With `json`, everything is printed as a
+    /// single object once done. With `ndjson`, a record is printed per file as it's processed,
+    /// followed by a summary record.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with_all = ["check", "stdin_path"])]
+    pub format: OutputFormat,
+
+    /// When to color the text output. `auto` colors it when stdout is a terminal, unless the
+    /// `NO_COLOR` environment variable is set.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// When to print the per-file output sorted by path instead of in the order files are done.
+    /// `auto` sorts it when stdout isn't a terminal, except for the streamed `ndjson` format.
+    #[arg(long, value_enum, default_value_t = SortChoice::Auto)]
+    pub sort: SortChoice,
+
+    /// Number of threads used to walk and process directories (default: picked automatically)
+    #[arg(short = 'j', long, value_name = "N")]
+    pub threads: Option<usize>,
+
+    /// Keep the modification time of files that get a new header
+    #[arg(long, overrides_with = "no_preserve_mtime")]
+    pub preserve_mtime: bool,
+
+    /// Turn off `preserve-mtime` from a .path-comment.toml
+    #[arg(long, overrides_with = "preserve_mtime")]
+    pub no_preserve_mtime: bool,
+
+    /// Only process the files staged in the git index, eg from a pre-commit hook.
+    /// Given PATHS restrict the selection to the files under them.
+    #[arg(long, group = "git_selector", conflicts_with_all = ["stdin_path", "no_git"])]
+    pub git_staged: bool,
+
+    /// Only process the files changed compared to REV (default: HEAD), staged or not, eg
+    /// `--git-changed=main`. Untracked files are left out.
+    #[arg(
+        long,
+        value_name = "REV",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "HEAD",
+        group = "git_selector",
+        conflicts_with_all = ["stdin_path", "no_git"]
+    )]
+    pub git_changed: Option<String>,
+
+    /// Only process the files tracked by git, skipping untracked ones without walking the
+    /// filesystem.
+    #[arg(long, group = "git_selector", conflicts_with_all = ["stdin_path", "no_git"])]
+    pub git_tracked: bool,
+
+    /// Only update the headers of the files renamed in the staged changes (eg with `git mv`),
+    /// or in RANGE when given, eg `--git-renamed=main..HEAD`.
+    #[arg(
+        long,
+        value_name = "RANGE",
+        num_args = 0..=1,
+        require_equals = true,
+        group = "git_selector",
+        conflicts_with_all = ["stdin_path", "no_git"]
+    )]
+    pub git_renamed: Option<Option<String>>,
+
+    /// Hook mode, for pre-commit: fix the given files in place, print only the files that
+    /// changed, and exit with a non-zero code if any did.
+    #[arg(long, conflicts_with_all = ["check", "stdin_path", "diff"])]
+    pub hook: bool,
+
+    /// Check mode: don't modify files, list files missing their path comment or with a stale one,
+    /// and exit with a non-zero code if there are any.
+    #[arg(long)]
+    pub check: bool,
+
+    /// Detect the comment style of files the extension config doesn't cover from their shebang
+    /// (eg `#!/usr/bin/env python3`) or vim/emacs modeline, see the `interpreter` config rules.
+    #[arg(long, overrides_with = "no_sniff")]
+    pub sniff: bool,
+
+    /// Turn off `sniff` from a .path-comment.toml
+    #[arg(long, overrides_with = "sniff")]
+    pub no_sniff: bool,
+
+    /// Force override a specific comment style to use (overrides config file). Custom styles
+    /// defined with `style` rules in the config file can be given by name too.
+    #[arg(short = 's', long, value_name = "STYLE", value_parser = StyleNameParser)]
+    pub comment_style: Option<String>,
 
     /// Print configured extensions styles, then exit.
     #[arg(short, long)]
     pub print_extensions: bool,
+
+    /// Settings from the `.path-comment.toml` files without a command line flag
+    #[arg(skip)]
+    pub project: ProjectConfig,
 }
 
 impl Args {
     pub fn parse() -> Self {
         <Args as Parser>::parse()
     }
+
+    /// The git selector replacing the filesystem walk, if any
+    pub fn git_selector(&self) -> Option<GitSelector> {
+        if self.git_staged {
+            Some(GitSelector::Staged)
+        } else if let Some(rev) = &self.git_changed {
+            Some(GitSelector::Changed(rev.clone()))
+        } else if self.git_tracked {
+            Some(GitSelector::Tracked)
+        } else {
+            self.git_renamed.clone().map(GitSelector::Renamed)
+        }
+    }
+
+    /// Whether stdout is reserved for machine-readable output (check results, filtered content,
+    /// diffs, JSON) or kept short (hook mode), in which case informational messages are not printed.
+    pub fn quiet_stdout(&self) -> bool {
+        self.check
+            || self.hook
+            || self.diff
+            || self.stdin_path.is_some()
+            || self.format != OutputFormat::Text
+    }
+}
+
+/// Accepts any style name for `--comment-style`, as custom styles are only known once the config
+/// is loaded, while still listing the built-in ones in the help
+#[derive(Copy, Clone, Debug)]
+struct StyleNameParser;
+
+impl TypedValueParser for StyleNameParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        StringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        let values = comments::Style::BUILTIN.into_iter().map(|style| {
+            let (start, end) = style.delimiters();
+            let help = format!("`{}`", format!("{} {}", start.trim(), end.trim()).trim());
+            PossibleValue::new(style.name()).help(help)
+        });
+        Some(Box::new(values))
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OutputFormat {
+    /// Colored, human readable report
+    #[default]
+    Text,
+    /// A single JSON object with every file record and the summary
+    Json,
+    /// A JSON record per line, streamed as files are processed
+    Ndjson,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum, Debug)]
+pub enum SortChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
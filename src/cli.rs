@@ -1,47 +1,81 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs,
-    io::{self, BufRead, BufReader},
+    env, fs,
+    io::{self, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicUsize, Ordering},
     },
 };
 
-use ignore::{DirEntry, WalkBuilder, WalkState}; // Added DirEntry import
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use ignore::{
+    DirEntry, WalkBuilder, WalkState,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
 
-use crate::{args, comments};
+use crate::{
+    args, comments,
+    config::Config,
+    encoding, find_git_root,
+    git::{self, GitFile, GitSelector},
+    ignores::{GlobFilters, IgnoreFiles, IgnoreMatch},
+    report::{FileOutcome, FileReport, SkipReason},
+    reporter::{self, Summary},
+    resolve, transform, write,
+};
 
 pub struct Cli {
     args: args::Args,
     base_dir: PathBuf,
     processed_count: Arc<AtomicUsize>,
     skipped_count: Arc<AtomicUsize>,
-    extension_styles: HashMap<String, comments::Style>,
+    config: Config,
     ignored_dirs: HashSet<String>,
+    gitignore: Gitignore,
+    ignore_files: IgnoreFiles,
+    glob_filters: GlobFilters,
+    header_template: Option<transform::HeaderTemplate>,
+    /// The style given with --comment-style, `None` if it isn't a known style
+    comment_style: Option<comments::Style>,
+    check_failures: Mutex<Vec<(String, CheckStatus)>>,
+    /// Directories pruned from the current walk, reported once it's done
+    skipped_dirs: Mutex<Vec<PathBuf>>,
+    /// Problems with the configuration, see [`Cli::warnings`]
+    warnings: Vec<String>,
 }
 
-const ANSI_RESET: &str = "\x1b[0m";
-
-fn added(s: &str) -> String {
-    const ANSI_GREEN: &str = "\x1b[32m";
-    format!("{ANSI_GREEN}+ {s}{ANSI_RESET}")
+/// Why a file failed `--check`
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum CheckStatus {
+    /// The file has no path comment at all
+    Missing,
+    /// The file has a path comment, but not the expected one (or extra ones to strip)
+    Stale,
 }
 
-fn removed(s: &str) -> String {
-    const ANSI_RED: &str = "\x1b[31m";
-    format!("{ANSI_RED}- {s}{ANSI_RESET}")
-}
+impl CheckStatus {
+    /// Classifies a header update that would change the file
+    pub fn of(update: &transform::HeaderUpdate) -> Self {
+        if update.had_header || !update.stripped.is_empty() {
+            CheckStatus::Stale
+        } else {
+            CheckStatus::Missing
+        }
+    }
 
-fn no_change(s: &str) -> String {
-    const ANSI_YELLOW: &str = "\x1b[33m";
-    format!("{ANSI_YELLOW} {s}{ANSI_RESET}")
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Missing => "missing",
+            CheckStatus::Stale => "stale",
+        }
+    }
 }
 
 const DEFAULT_IGNORE_CONFIG: &str = include_str!("ignore.cfg");
 
-fn load_ignored_dirs(gitignore_path: Option<&Path>) -> HashSet<String> {
+fn load_ignored_dirs() -> HashSet<String> {
     let mut ignored = HashSet::new();
 
     for line in DEFAULT_IGNORE_CONFIG.lines() {
@@ -51,53 +85,157 @@ fn load_ignored_dirs(gitignore_path: Option<&Path>) -> HashSet<String> {
         }
     }
 
-    // Merge from .gitignore if provided and exists
-    if let Some(path) = gitignore_path {
-        if path.is_file() {
-            println!("Merging ignore rules from {}", path.display());
-            if let Ok(file) = fs::File::open(path) {
-                let reader = BufReader::new(file);
-                for line_content in reader.lines().map_while(Result::ok) {
-                    // Handle inline comments by splitting at '#'
-                    let line_before_comment = line_content.split('#').next().unwrap_or("").trim();
-
-                    // Use line_before_comment for checks instead of trimmed
-                    if !line_before_comment.is_empty()
-                        // No need to check for '#' start anymore as split handles it
-                        && !line_before_comment.contains('*')
-                        && !line_before_comment.contains('?')
-                        && !line_before_comment.contains('[')
-                        && !line_before_comment.contains('!')
-                        && !line_before_comment.contains('\\')
-                    {
-                        // Remove trailing slash if present
-                        let dir_name = line_before_comment
-                            .strip_suffix('/')
-                            .unwrap_or(line_before_comment);
-                        // Ensure we don't insert empty strings if a line was just a comment or whitespace
-                        if !dir_name.is_empty() {
-                            ignored.insert(dir_name.to_string());
-                        }
-                    }
-                }
-            } else {
-                eprintln!(
-                    "Warning: Could not read .gitignore file at {}",
-                    path.display()
-                );
-            }
-        } else {
-            // Check if the path components contain .git, otherwise don't warn (e.g. no .git found case)
-            if path.components().any(|comp| comp.as_os_str() == ".git") {
-                eprintln!(
-                    "Warning: .gitignore path specified but not found or not a file: {}",
-                    path.display()
-                );
-            }
+    ignored
+}
+
+/// Builds a matcher with full gitignore semantics (globs, negation, anchoring) from the
+/// `.gitignore` in the base directory. Nested `.gitignore` files are handled by the walker.
+fn load_gitignore(
+    gitignore_path: Option<&Path>,
+    base_dir: &Path,
+    warnings: &mut Vec<String>,
+) -> Gitignore {
+    let Some(path) = gitignore_path.filter(|path| path.is_file()) else {
+        return Gitignore::empty();
+    };
+
+    let mut builder = GitignoreBuilder::new(base_dir);
+    if let Some(e) = builder.add(path) {
+        warnings.push(format!("Could not fully read {}: {e}", path.display()));
+    }
+    builder.build().unwrap_or_else(|e| {
+        warnings.push(format!("Invalid ignore rules in {}: {e}", path.display()));
+        Gitignore::empty()
+    })
+}
+
+/// Bytes read from each end of a file to sniff its style, plenty for a shebang and modelines
+const SNIFF_BYTES: u64 = 8 * 1024;
+
+/// The start and end of the file at `path` as text for sniffing its style, the whole file if
+/// it's small. Returns `None` for binary files.
+fn sniff_file(path: &Path, configured: Option<&'static Encoding>) -> io::Result<Option<String>> {
+    let mut file = fs::File::open(path)?;
+    let mut head = Vec::new();
+    (&mut file).take(SNIFF_BYTES).read_to_end(&mut head)?;
+    let encoding = encoding::detect(&head, configured);
+    // A NUL byte in text only happens in UTF-16
+    if head.contains(&0) && encoding != UTF_16LE && encoding != UTF_16BE {
+        return Ok(None);
+    }
+
+    // The rest of a small file, or just its end
+    let cut = file.metadata()?.len() > 2 * SNIFF_BYTES;
+    if cut {
+        file.seek(SeekFrom::End(-(SNIFF_BYTES as i64)))?;
+    }
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    // Cutting a file may split a character, which only garbles the lines at the cut
+    let (head, _) = encoding.decode_without_bom_handling(&head);
+    let (tail, _) = encoding.decode_without_bom_handling(&tail);
+    let separator = if cut { "\n" } else { "" };
+    Ok(Some(format!("{head}{separator}{tail}")))
+}
+
+/// The base directory of a run, see [`resolve_base_dir`]
+#[derive(Clone, Debug)]
+pub struct BaseDir {
+    /// Canonical directory the headers are relative to
+    pub dir: PathBuf,
+    /// The `.gitignore` whose rules are merged, if any
+    pub gitignore_path: Option<PathBuf>,
+    /// Whether `dir` is the git repository root found from the first target, rather than
+    /// `--base` or the working directory
+    pub from_git: bool,
+}
+
+/// Determines the base directory for relative paths and the `.gitignore` to merge, from the
+/// targets and flags in `args`. Errors are formatted for printing.
+pub fn resolve_base_dir(args: &args::Args) -> Result<BaseDir, String> {
+    let current_dir =
+        || env::current_dir().map_err(|e| format!("Failed to get current directory: {e}"));
+
+    // Fail early on targets that don't exist rather than half-processing the list
+    for target in &args.paths {
+        if let Err(e) = Path::new(target).metadata() {
+            return Err(format!("Error accessing target '{target}': {e}"));
         }
     }
 
-    ignored
+    // The git selectors need a repository to list the files from
+    if let Some(selector) = args.git_selector() {
+        let first_target = args.paths.first().map_or(".", String::as_str);
+        let in_repo = Path::new(first_target)
+            .canonicalize()
+            .ok()
+            .and_then(|target| find_git_root(&target))
+            .is_some();
+        if !in_repo {
+            return Err(format!(
+                "{} requires a git repository, none found upwards from '{first_target}'",
+                selector.flag()
+            ));
+        }
+    }
+
+    let mut git_base_used = false; // Track if base was determined via .git
+
+    // Determine the base directory for relative paths
+    let base_dir = match args.base {
+        Some(ref base) => PathBuf::from(base),
+        None => {
+            if args.no_git {
+                // Git search disabled, use CWD
+                current_dir()?
+            } else {
+                // Try to find .git root starting from the first target, the others
+                // resolve their own git root while processing
+                let first_target = args
+                    .stdin_path
+                    .as_ref()
+                    .or(args.paths.first())
+                    .map_or(".", String::as_str);
+                // The stdin path is only logical, it doesn't have to exist on disk
+                let absolute_target_dir = if args.stdin_path.is_some() {
+                    std::path::absolute(first_target)
+                } else {
+                    Path::new(first_target).canonicalize()
+                }
+                .map_err(|e| format!("Error accessing target '{first_target}': {e}"))?;
+
+                if let Some(git_root) = find_git_root(&absolute_target_dir) {
+                    git_base_used = true;
+                    git_root
+                } else {
+                    // No .git found, fall back to CWD
+                    current_dir()?
+                }
+            }
+        }
+    };
+
+    // Canonicalize base_dir to handle relative paths robustly
+    let base_dir = base_dir.canonicalize().map_err(|e| {
+        format!(
+            "Error accessing base directory '{}': {}",
+            base_dir.display(),
+            e
+        )
+    })?;
+
+    let gitignore_path = if git_base_used && !args.no_ignore_merge {
+        Some(base_dir.join(".gitignore"))
+    } else {
+        None
+    };
+
+    Ok(BaseDir {
+        dir: base_dir,
+        gitignore_path,
+        from_git: git_base_used,
+    })
 }
 
 impl Cli {
@@ -106,26 +244,65 @@ impl Cli {
         base_dir: PathBuf,
         gitignore_path: Option<PathBuf>, // Pass potential .gitignore path
     ) -> Self {
-        // Load extension styles from config file or use default
-        let extension_styles = if let Some(config_path) = &args.config_file {
+        let mut warnings = args.project.warnings.clone();
+
+        // Load the user config file, if any
+        let config_content = args.config_file.as_ref().and_then(|config_path| {
             match fs::read_to_string(config_path) {
-                Ok(content) => {
-                    println!("Loading config from {config_path}");
-                    comments::parse_config(&content)
-                }
+                Ok(content) => Some(content),
                 Err(e) => {
-                    eprintln!("Error reading config file {config_path}: {e}");
-                    println!("Using default configuration");
-                    comments::default_config()
+                    warnings.push(format!(
+                        "Could not read config file {config_path}: {e}, using the default configuration"
+                    ));
+                    None
                 }
             }
-        } else {
-            // Use default config
-            comments::default_config()
+        });
+
+        let mut config = match &config_content {
+            Some(content) => Config::parse(content),
+            None => Config::default(),
         };
+        warnings.extend_from_slice(&config.warnings);
+
+        // Project config styles are merged over the ones from the config file or the defaults
+        for (ext, style_str) in &args.project.styles {
+            match config.extension_styles.parse_style(style_str) {
+                Some(style) => config.extension_styles.insert(ext, style, &mut warnings),
+                None => warnings.push(format!(
+                    "Unknown comment style '{style_str}' for '.{ext}' in project config, skipping"
+                )),
+            }
+        }
+        for (name, style_str) in &args.project.names {
+            match config.extension_styles.parse_style(style_str) {
+                Some(style) => config.extension_styles.insert_name(name, style),
+                None => warnings.push(format!(
+                    "Unknown comment style '{style_str}' for '{name}' in project config, skipping"
+                )),
+            }
+        }
+        for (target, label) in &args.project.encodings {
+            match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => config.encodings.add(target, encoding, &mut warnings),
+                None => warnings.push(format!(
+                    "Unknown encoding '{label}' in project config, skipping"
+                )),
+            }
+        }
+        let header_template = args.project.header.as_ref().and_then(|header| {
+            let template = transform::HeaderTemplate::parse(header);
+            if template.is_none() {
+                warnings.push(format!(
+                    "Header template '{header}' must contain {} once, ignoring it",
+                    transform::PATH_PLACEHOLDER
+                ));
+            }
+            template
+        });
 
         // If extensions are specified in args, filter to only those
-        let extension_styles = if let Some(extensions) = &args.extensions {
+        if let Some(extensions) = &args.extensions {
             let specified_extensions: Vec<String> = extensions
                 .split(',')
                 .map(|e| e.trim().to_lowercase())
@@ -133,30 +310,48 @@ impl Cli {
 
             let mut filtered = HashMap::new();
             for ext in &specified_extensions {
-                if let Some(&style) = extension_styles.get(ext) {
+                if let Some(&style) = config.extension_styles.extensions.get(ext) {
                     filtered.insert(ext.clone(), style);
                 } else {
                     // Default to slash comment style if not found
-                    eprintln!(
-                        "Warning: Extension '.{}' specified but no configuration found, defaulting to '//' style.",
+                    warnings.push(format!(
+                        "Extension '.{}' specified but no configuration found, defaulting to '//' style",
                         ext
-                    );
+                    ));
                     filtered.insert(ext.clone(), comments::Style::Slash);
                 }
             }
-            filtered
-        } else {
-            extension_styles
-        };
+            config.extension_styles.extensions = filtered;
+            config.extension_styles.filenames_exact.clear();
+            config.extension_styles.filenames.clear();
+        }
+
+        // Custom styles from the config can be given by name
+        let comment_style = args
+            .comment_style
+            .as_deref()
+            .and_then(|name| config.extension_styles.parse_style(name));
 
         // Load ignored directories (potentially merging .gitignore)
-        let ignored_dirs = load_ignored_dirs(gitignore_path.as_deref());
+        let ignored_dirs = load_ignored_dirs();
+        let ignore_files =
+            IgnoreFiles::new(&base_dir).with_project_rules(args.project.ignore_rules.clone());
+        let glob_filters = GlobFilters::new(&args.include, &args.exclude, &mut warnings);
+        let gitignore = load_gitignore(gitignore_path.as_deref(), &base_dir, &mut warnings);
 
         Self {
             args,
             base_dir,
-            extension_styles,
+            config,
             ignored_dirs, // Use loaded set
+            gitignore,
+            ignore_files,
+            glob_filters,
+            header_template,
+            comment_style,
+            check_failures: Mutex::new(Vec::new()),
+            skipped_dirs: Mutex::new(Vec::new()),
+            warnings,
             processed_count: Arc::new(AtomicUsize::new(0)),
             skipped_count: Arc::new(AtomicUsize::new(0)),
         }
@@ -175,31 +370,67 @@ impl Cli {
         Arc::new(Self::new(args, base_dir, gitignore_path))
     }
 
+    /// The comment styles and placement rules in use
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Problems found in the config files, project config and filters while setting up, one
+    /// per skipped rule or file. The library doesn't print them, the binary does.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
     pub fn should_process_file(&self, path: &Path) -> bool {
-        if let Some(extension) = path.extension() {
-            let ext = extension.to_string_lossy().to_lowercase();
-            self.extension_styles.contains_key(&ext)
-        } else {
-            false
-        }
+        self.config.style_for(path).is_some()
     }
 
     pub fn determine_comment_style(&self, path: &Path) -> Option<comments::Style> {
         // If user specified a style on command line, use that
-        if let Some(style) = self.args.comment_style {
+        if let Some(style) = self.comment_style {
             return Some(style);
         }
 
         // Otherwise, look up in our extension map
-        if let Some(extension) = path.extension() {
-            let ext = extension.to_string_lossy().to_lowercase();
-            self.extension_styles.get(&ext).copied()
-        } else {
-            None
+        self.config.style_for(path)
+    }
+
+    /// With `--sniff`, detects the style of a file without a path-based one from its content
+    pub fn sniff_comment_style(&self, content: &str) -> Option<comments::Style> {
+        if !self.args.sniff {
+            return None;
         }
+        self.config.sniff_style(content)
+    }
+
+    /// Picks the final style and header position of a file from its content, on top of
+    /// [`Self::determine_comment_style`]: multi-language files get the style of what they open
+    /// with. A style forced with --comment-style is used as is.
+    pub fn resolve_comment_style(
+        &self,
+        path: &Path,
+        content: &str,
+        style: comments::Style,
+    ) -> resolve::Resolution<'_> {
+        if self.comment_style.is_some() {
+            return resolve::Resolution::fixed(style);
+        }
+        self.config.resolve(path, content, style)
     }
 
     pub fn should_skip_directory(&self, path: &Path) -> bool {
+        // .pathcommentignore rules win over everything else, a `!pattern` there can re-include
+        // a directory that's ignored by default
+        match self.ignore_files.matched(path, true) {
+            IgnoreMatch::Ignore => return true,
+            IgnoreMatch::Whitelist => return false,
+            IgnoreMatch::None => {}
+        }
+
+        if self.is_gitignored(path, true) {
+            return true;
+        }
+
         if self.args.force {
             return false;
         }
@@ -216,219 +447,124 @@ impl Cli {
         false
     }
 
-    pub fn process_file(&self, path: &Path) -> io::Result<()> {
-        if !self.should_process_file(path) {
-            // Don't increment skipped count here, it's not explicitly skipped due to config/state,
-            // it just doesn't match the criteria. Let the caller handle skipping if needed.
-            return Ok(());
-        }
-
-        // Determine the comment style for this file
-        let comment_style = match self.determine_comment_style(path) {
-            Some(style) => style,
-            None => {
-                // This case should ideally not be reached if should_process_file is true,
-                // but handle defensively.
-                eprintln!(
-                    "Internal Error: Could not determine comment style for {} (but should_process_file was true). Skipping.",
-                    path.display()
-                );
-                self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                return Ok(());
-            }
-        };
-
-        // Get the comment delimiters
-        let (comment_start, comment_end) = match comments::DELIMITERS.get(&comment_style) {
-            Some(delimiters) => delimiters,
-            None => {
-                // This should also ideally not be reached if determine_comment_style succeeded
-                eprintln!(
-                    "Internal Error: No delimiters found for comment style {:?}. Skipping {}.",
-                    comment_style,
-                    path.display()
-                );
-                self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                return Ok(());
-            }
-        };
-
-        let processed = format!("{}", path.display());
-
-        // Read the file content
-        let content = match fs::read_to_string(path) {
-            Ok(c) => c,
-            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
-                // Likely a binary file or non-UTF8 encoding
-                // Use no_change style for visual consistency
-                println!("{} {}", processed, no_change("Skipped non-UTF8 file"));
-                self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                return Ok(());
-            }
-            Err(e) => return Err(e), // Propagate other read errors
-        };
-
-        // Calculate the relative path
-        let rel_path = match path.strip_prefix(&self.base_dir) {
-            Ok(rel) => rel.to_path_buf(),
-            // If stripping fails (e.g., path is not under base_dir), use the full path.
-            // This might happen if base_dir logic changes or symlinks are involved.
-            Err(_) => path.to_path_buf(),
-        };
-        // Convert to string, ensuring forward slashes for consistency
-        let rel_path_str = rel_path.to_string_lossy().replace('\\', "/");
-        let rel_path_str = rel_path_str.trim_start_matches("./").to_string();
-
-        // Build the new header comment
-        let first_line = format!("{comment_start}{rel_path_str}{comment_end}");
-
-        // Split the content into lines for easier manipulation
-        let lines: Vec<&str> = content.lines().collect();
-
-        // First, check if the first line is exactly our desired comment
-        let already_had_path_comment = !lines.is_empty() && lines[0].trim() == first_line.trim();
-        if already_had_path_comment {
-            // If the correct comment is already there AND we are not stripping other potential
-            // path comments, we can skip modification entirely.
-            if self.args.keep || !self.args.clean {
-                println!("{processed} {}", no_change(&first_line));
-                self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                return Ok(());
-            }
-            // If strip is true, we still need to continue to check for *other* path comments.
-        }
-
-        // Get the regex for the current comment style
-        let path_comment_re = match comments::REGEXES.get(&comment_style) {
-            Some(regex) => regex,
-            None => {
-                // This should also ideally not be reached
-                eprintln!(
-                    "Internal Error: No regex found for comment style {:?}. Skipping {}.",
-                    comment_style,
-                    path.display()
-                );
-                self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                return Ok(());
-            }
-        };
+    /// Whether a file is excluded by a `.pathcommentignore`. Unlike the other ignore rules,
+    /// these also apply to files given explicitly on the command line.
+    pub fn should_skip_file(&self, path: &Path) -> bool {
+        self.ignore_files.matched(path, false) == IgnoreMatch::Ignore
+    }
 
-        // Find all existing path-looking comments *if* stripping is enabled
-        let mut path_comment_line_numbers = Vec::new();
-        if !self.args.keep {
-            for (line_num, line) in lines.iter().enumerate() {
-                if line_num == 0 && already_had_path_comment {
-                    continue;
-                }
-                // Use trim() to ignore leading/trailing whitespace when matching
-                if path_comment_re.is_match(line.trim()) {
-                    path_comment_line_numbers.push(line_num);
-                }
-            }
+    /// Whether `path` passes the `--include`/`--exclude` globs, which match the path
+    /// relative to `base_dir`
+    pub fn passes_glob_filters(&self, path: &Path, base_dir: &Path, is_dir: bool) -> bool {
+        let rel_path = transform::header_path(path, base_dir);
+        if is_dir {
+            self.glob_filters.keep_dir(&rel_path)
+        } else {
+            self.glob_filters.keep_file(&rel_path)
         }
+    }
 
-        // --- Visualization ---
-
-        if path_comment_line_numbers.is_empty() {
-            // First line is identical, show as no change
-            if already_had_path_comment {
-                if self.args.clean {
-                    println!("{processed} {}", removed(&first_line));
-                } else {
-                    println!("{processed} {}", no_change(&first_line));
-                }
-            } else if self.args.clean {
-                println!("{processed} {}", no_change("(no change)"));
-            } else {
-                println!("{processed} {}", added(&first_line));
-            }
+    /// Whether `path` (or one of its parents) is ignored by the base directory's `.gitignore`
+    pub fn is_gitignored(&self, path: &Path, is_dir: bool) -> bool {
+        let root = self.gitignore.path();
+        // The matcher only understands paths under the base directory
+        let path = if path.starts_with(root) {
+            path.to_path_buf()
         } else {
-            println!("{processed} ");
-
-            if already_had_path_comment {
-                if self.args.clean {
-                    println!("{}", removed(&first_line));
-                } else {
-                    println!("{}", no_change(&first_line));
-                }
-            }
-            // Show other path comments being removed (if stripping)
-            if !self.args.keep {
-                for &line_num in &path_comment_line_numbers {
-                    println!("{}", removed(lines[line_num]));
-                }
-            }
-
-            if !already_had_path_comment && !self.args.clean {
-                println!("{}", added(&first_line));
+            match path.canonicalize() {
+                Ok(abs) if abs.starts_with(root) => abs,
+                _ => return false,
             }
+        };
+        self.gitignore
+            .matched_path_or_any_parents(&path, is_dir)
+            .is_ignore()
+    }
 
-            println!();
+    /// Returns the base directory headers are made relative to for files under `target`.
+    /// Without an explicit `--base`, each target gets the git root it lives in.
+    pub fn base_dir_for(&self, target: &Path) -> PathBuf {
+        if self.args.base.is_some() || self.args.no_git {
+            return self.base_dir.clone();
         }
+        target
+            .canonicalize()
+            .ok()
+            .and_then(|target| find_git_root(&target))
+            .unwrap_or_else(|| self.base_dir.clone())
+    }
 
-        // --- Write Output ---
+    pub fn process_file(&self, path: &Path) -> io::Result<FileReport> {
+        self.process_file_with_base(path, &self.base_dir)
+    }
 
-        // Build the final content lines vector
-        // Start with the new first line we already added
-        let mut final_content_lines: Vec<&str> = if !self.args.clean {
-            vec![first_line.as_str()]
-        } else {
-            vec![]
+    /// Same as `process_file`, but with the header path relative to `base_dir`
+    pub fn process_file_with_base(&self, path: &Path, base_dir: &Path) -> io::Result<FileReport> {
+        // Reports use the path the header shows, whichever way the file was found
+        let rel_path_str = transform::header_path(path, base_dir);
+        let report = |outcome| FileReport {
+            path: PathBuf::from(&rel_path_str),
+            outcome,
+            renamed_from: None,
         };
 
-        // Add original lines, skipping the ones identified as path comments (if stripping)
-        for (i, line) in lines.iter().enumerate() {
-            let is_path_comment_to_strip =
-                !self.args.keep && path_comment_line_numbers.contains(&i);
-
-            if i == 0 {
-                // Skip adding the original line 0 if:
-                // 2. The first line wasn't changed (meaning the original line 0 was already correct)
-                if is_path_comment_to_strip || already_had_path_comment {
-                    continue;
-                }
-                // Otherwise (first line changed BUT original line 0 wasn't a path comment), add original line 0
-            }
+        let skip = |reason| {
+            self.skipped_count.fetch_add(1, Ordering::Relaxed);
+            Ok(report(FileOutcome::Skipped(reason)))
+        };
 
-            // For lines other than 0, or if line 0 meets criteria above:
-            // Add the line if it's not a path comment we're stripping
-            if !is_path_comment_to_strip {
-                final_content_lines.push(line);
-            }
+        // Determine the comment style for this file, the content is only needed with --sniff
+        let path_style = self
+            .determine_comment_style(path)
+            .filter(|_| self.should_process_file(path));
+        if path_style.is_none() && !self.args.sniff {
+            // File doesn't match our extension list, count as skipped for summary
+            return skip(SkipReason::NoStyle);
         }
 
-        // Join the lines back together
-        let mut new_content = final_content_lines.join("\n");
+        // Sniffing only needs both ends of the file, so files without a style (often
+        // binaries) aren't read whole
+        let configured_encoding = self.config.encoding_for(path, base_dir);
+        let comment_style = match path_style {
+            Some(style) => style,
+            None => match sniff_file(path, configured_encoding)?
+                .and_then(|text| self.sniff_comment_style(&text))
+            {
+                Some(style) => style,
+                None => return skip(SkipReason::NoStyle),
+            },
+        };
 
-        // Preserve trailing newline if original had one or was empty
-        if content.ends_with('\n') || content.is_empty() {
-            // Ensure only one trailing newline
-            if !new_content.ends_with('\n') {
-                new_content.push('\n');
-            }
-        } else {
-            // Original didn't end with newline, ensure new one doesn't either
-            // (unless it's now empty, which join won't produce anyway)
-            if new_content.ends_with('\n') && !new_content.is_empty() {
-                new_content.pop();
-            }
-        }
+        // Read the file content, decoding it from its BOM or configured encoding
+        let bytes = fs::read(path)?;
+        let Some(decoded) = encoding::decode(&bytes, configured_encoding) else {
+            // Likely a binary file or an unconfigured encoding
+            return skip(SkipReason::NonUtf8);
+        };
 
-        // Compare final content with original before writing
-        if content == new_content {
-            // This can happen if strip=true but the only path comment found was
-            // already the correct first line. needs_write might have been true initially,
-            // but the final result is identical.
-            if already_had_path_comment {
-                // If the first line was already correct...
-                println!("{processed} {}", no_change(&first_line)); // Re-print no_change msg
-            } // Otherwise the changes were already printed.
-            self.skipped_count.fetch_add(1, Ordering::Relaxed);
-            return Ok(());
+        let resolution = self.resolve_comment_style(path, &decoded.text, comment_style);
+        if resolution.style == comments::Style::Docstring
+            && transform::has_docstring(&decoded.text, &self.config.preambles_for(path))
+        {
+            return skip(SkipReason::Docstring);
         }
+        let update = self.apply_header(&decoded.text, path, &rel_path_str, &resolution);
+        let diff = (self.args.diff && update.changed)
+            .then(|| transform::unified_diff(&decoded.text, &update.content, &rel_path_str));
 
-        if !self.args.dry_run {
-            match fs::write(path, &new_content) {
+        if !update.changed {
+            self.skipped_count.fetch_add(1, Ordering::Relaxed);
+        } else if self.args.check {
+            // Never write in check mode, just record why the file fails the check
+            let status = CheckStatus::of(&update);
+            self.check_failures
+                .lock()
+                .unwrap()
+                .push((rel_path_str.clone(), status));
+        } else if !self.args.dry_run {
+            let written = encoding::encode(&update.content, decoded.encoding)
+                .and_then(|bytes| write::write_atomic(path, bytes, self.args.preserve_mtime));
+            match written {
                 Ok(_) => {
                     self.processed_count.fetch_add(1, Ordering::Relaxed);
                 }
@@ -436,7 +572,7 @@ impl Cli {
                     eprintln!("Error writing file {}: {}", path.display(), e);
                     // Treat as skipped if write fails
                     self.skipped_count.fetch_add(1, Ordering::Relaxed);
-                    return Err(e); // Propagate write error
+                    return Ok(report(FileOutcome::Skipped(SkipReason::WriteError)));
                 }
             }
         } else {
@@ -444,7 +580,18 @@ impl Cli {
             self.processed_count.fetch_add(1, Ordering::Relaxed);
         }
 
-        Ok(())
+        Ok(report(FileOutcome::Processed {
+            style: resolution.style,
+            update,
+            diff,
+        }))
+    }
+
+    /// Files that failed `--check`, sorted by path so the output is stable
+    pub fn check_failures(&self) -> Vec<(String, CheckStatus)> {
+        let mut failures = self.check_failures.lock().unwrap().clone();
+        failures.sort();
+        failures
     }
 
     pub fn get_stats(&self) -> (usize, usize) {
@@ -455,7 +602,7 @@ impl Cli {
     }
 
     fn print_extension_styles(&self) {
-        if self.extension_styles.is_empty() {
+        if self.config.extension_styles.is_empty() {
             println!("No file extensions configured.");
             return;
         }
@@ -464,71 +611,396 @@ impl Cli {
 
         println!("File extensions that will be processed:");
         let mut extensions: Vec<(&String, &comments::Style)> =
-            self.extension_styles.iter().collect();
+            self.config.extension_styles.extensions.iter().collect();
         extensions.sort_by(|a, b| a.0.cmp(b.0)); // Sort by extension
 
         for (ext, style) in extensions {
             let (start, end) = style.delimiters();
             println!("  .{ext}: {start}{end}");
         }
+        let mut names: Vec<(&String, &comments::Style)> = self
+            .config
+            .extension_styles
+            .filenames_exact
+            .iter()
+            .collect();
+        names.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, style) in names {
+            let (start, end) = style.delimiters();
+            println!("  {name}: {start}{end}");
+        }
+        // File name globs are listed in order of increasing precedence
+        for rule in &self.config.extension_styles.filenames {
+            let (start, end) = rule.style.delimiters();
+            println!("  {}: {start}{end}", rule.pattern);
+        }
         println!();
     }
 
-    pub fn run(self: &Arc<Self>) {
+    /// Processes all files and returns the process exit code
+    pub fn run(self: &Arc<Self>) -> i32 {
         if self.args.print_extensions {
             self.print_extension_styles();
-            return;
+            return 0;
+        }
+
+        if let Some(name) = &self.args.comment_style
+            && self.comment_style.is_none()
+        {
+            eprintln!(
+                "Error: Unknown comment style '{name}', see --help for the built-in styles or define it with a `style` rule in the config file"
+            );
+            return 1;
+        }
+
+        if let Some(stdin_path) = &self.args.stdin_path {
+            return self.run_stdin(Path::new(stdin_path));
+        }
+
+        // Check and diff modes keep stdout machine-parseable, so skip the banner
+        if !self.args.quiet_stdout()
+            && let Err(e) = self.print_banner()
+        {
+            return output_error_code(&e);
+        }
+
+        // Check mode only lists the offending files once the walk is done
+        if self.args.check {
+            return match self
+                .process_paths(&|_| Ok(()))
+                .and_then(|()| self.report_check_failures())
+            {
+                Ok(code) => code,
+                Err(e) => output_error_code(&e),
+            };
+        }
+
+        let reporter = reporter::for_args(&self.args);
+        let (processed, skipped) = match self.process_paths(&|report| reporter.report(report)) {
+            Ok(()) => self.get_stats(),
+            Err(e) => return output_error_code(&e),
+        };
+        let summary = Summary {
+            processed,
+            skipped,
+            dry_run: self.args.dry_run,
+        };
+        if let Err(e) = reporter.finish(&summary) {
+            return output_error_code(&e);
         }
 
-        println!("Processing directory: {}", self.args.dir);
-        println!("Using base directory: {}", self.base_dir.display());
+        // pre-commit fails the hook when files were modified, so they can be reviewed and staged
+        if self.args.hook && processed > 0 {
+            return 1;
+        }
+        0
+    }
+
+    fn print_banner(&self) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        match self.args.git_selector() {
+            Some(selector) => writeln!(out, "Processing: files selected by {}", selector.flag())?,
+            None => writeln!(out, "Processing: {}", self.args.paths.join(", "))?,
+        }
+        writeln!(out, "Using base directory: {}", self.base_dir.display())?;
         if self.args.dry_run {
-            println!("Dry run mode enabled. No files will be modified.");
+            writeln!(out, "Dry run mode enabled. No files will be modified.")?;
         }
         if self.args.force {
-            println!("Force mode enabled. Ignoring default directory skip list.");
+            writeln!(
+                out,
+                "Force mode enabled. Ignoring default directory skip list."
+            )?;
+        }
+        writeln!(out) // Blank line for readability before processing starts
+    }
+
+    /// Processes every target path, calling `on_report` for each file as soon as it's done.
+    /// Directories are walked in parallel, so `on_report` is called from worker threads.
+    /// The first error returned by `on_report` stops the run and is returned.
+    pub fn process_paths(
+        self: &Arc<Self>,
+        on_report: &(dyn Fn(&FileReport) -> io::Result<()> + Sync),
+    ) -> io::Result<()> {
+        if let Some(selector) = self.args.git_selector() {
+            return self.process_git_files(&selector, on_report);
+        }
+
+        for target in &self.args.paths {
+            let target = Path::new(target);
+            let base_dir = self.base_dir_for(target);
+
+            if target.is_file() {
+                // Files given explicitly skip the walker and its directory filters
+                if !self.should_skip_file(target)
+                    && self.passes_glob_filters(target, &base_dir, false)
+                {
+                    self.process_entry(target, &base_dir, None, on_report)?;
+                }
+            } else {
+                self.walk_directory(target, base_dir, on_report)?;
+            }
         }
-        println!(); // Blank line for readability before processing starts
+        Ok(())
+    }
 
-        // Build the walker
-        let mut builder = WalkBuilder::new(&self.args.dir);
+    /// Processes the files selected from git under each target (or the current directory).
+    /// Like files given explicitly, they skip the directory filters.
+    fn process_git_files(
+        &self,
+        selector: &GitSelector,
+        on_report: &(dyn Fn(&FileReport) -> io::Result<()> + Sync),
+    ) -> io::Result<()> {
+        let targets = if self.args.paths.is_empty() {
+            vec![".".to_string()]
+        } else {
+            self.args.paths.clone()
+        };
+
+        // Each repository is only listed once, and each file processed once
+        let mut listed: HashMap<PathBuf, Vec<GitFile>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for target in &targets {
+            let Some((target, repo_root)) = Path::new(target)
+                .canonicalize()
+                .ok()
+                .and_then(|target| find_git_root(&target).map(|root| (target, root)))
+            else {
+                eprintln!("Warning: '{target}' is not in a git repository, skipping");
+                continue;
+            };
+
+            let files = listed.entry(repo_root.clone()).or_insert_with(|| {
+                git::list_files(&repo_root, selector).unwrap_or_else(|e| {
+                    eprintln!("Error listing files of {}: {e}", repo_root.display());
+                    Vec::new()
+                })
+            });
+
+            for GitFile { path, renamed_from } in files.iter() {
+                if !path.starts_with(&target) || !path.is_file() || !seen.insert(path.clone()) {
+                    continue;
+                }
+                let base_dir = self.base_dir_for(path);
+                if !self.should_skip_file(path) && self.passes_glob_filters(path, &base_dir, false)
+                {
+                    self.process_entry(path, &base_dir, renamed_from.as_deref(), on_report)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes every target path and returns the reports for all files, in no particular order
+    pub fn collect_reports(self: &Arc<Self>) -> Vec<FileReport> {
+        let reports = Mutex::new(Vec::new());
+        // Collecting never fails
+        let _ = self.process_paths(&|report| {
+            reports.lock().unwrap().push(report.clone());
+            Ok(())
+        });
+        reports.into_inner().unwrap()
+    }
+
+    /// Applies the header to `content` using this run's options and the preambles for `path`
+    fn apply_header(
+        &self,
+        content: &str,
+        path: &Path,
+        rel_path: &str,
+        resolution: &resolve::Resolution,
+    ) -> transform::HeaderUpdate {
+        let rules = self.config.preambles_for(path);
+        let options = resolution.options(transform::HeaderOptions {
+            keep: self.args.keep,
+            clean: self.args.clean,
+            template: self.header_template.as_ref(),
+            ..Default::default()
+        });
+        transform::apply_header(content, rel_path, resolution.style, &rules, options)
+    }
+
+    /// Filters content from stdin to stdout, as if it were the file at `logical_path`.
+    /// Content of files that wouldn't be processed is passed through unchanged.
+    fn run_stdin(&self, logical_path: &Path) -> i32 {
+        let mut bytes = Vec::new();
+        if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+            eprintln!("Error reading stdin: {e}");
+            return 1;
+        }
+
+        let no_style = || {
+            eprintln!(
+                "No comment style configured for {}, passing content through unchanged",
+                logical_path.display()
+            );
+            write_stdout(&bytes)
+        };
+        let path_style = self
+            .determine_comment_style(logical_path)
+            .filter(|_| self.comment_style.is_some() || self.should_process_file(logical_path));
+        if path_style.is_none() && !self.args.sniff {
+            return no_style();
+        }
+
+        let absolute_path = std::path::absolute(logical_path).unwrap_or(logical_path.to_path_buf());
+        let configured_encoding = self.config.encoding_for(&absolute_path, &self.base_dir);
+        let Some(decoded) = encoding::decode(&bytes, configured_encoding) else {
+            eprintln!(
+                "Could not decode {}, passing content through unchanged",
+                logical_path.display()
+            );
+            return write_stdout(&bytes);
+        };
+        let Some(style) = path_style.or_else(|| self.sniff_comment_style(&decoded.text)) else {
+            return no_style();
+        };
+        let rel_path = transform::header_path(&absolute_path, &self.base_dir);
+        let resolution = self.resolve_comment_style(logical_path, &decoded.text, style);
+        if resolution.style == comments::Style::Docstring
+            && transform::has_docstring(&decoded.text, &self.config.preambles_for(logical_path))
+        {
+            eprintln!(
+                "{} already has a docstring, passing content through unchanged",
+                logical_path.display()
+            );
+            return write_stdout(&bytes);
+        }
+        let update = self.apply_header(&decoded.text, logical_path, &rel_path, &resolution);
+
+        if self.args.check {
+            if !update.changed {
+                return 0;
+            }
+            println!("{}: {rel_path}", CheckStatus::of(&update).as_str());
+            return 1;
+        }
+
+        if self.args.diff {
+            if update.changed {
+                print!(
+                    "{}",
+                    transform::unified_diff(&decoded.text, &update.content, &rel_path)
+                );
+            }
+            return 0;
+        }
+
+        match encoding::encode(&update.content, decoded.encoding) {
+            Ok(bytes) => write_stdout(&bytes),
+            Err(e) => {
+                eprintln!("Error encoding {}: {e}", logical_path.display());
+                1
+            }
+        }
+    }
+
+    /// Processes a single file found by the walker or given on the command line
+    fn process_entry(
+        &self,
+        path: &Path,
+        base_dir: &Path,
+        renamed_from: Option<&Path>,
+        on_report: &(dyn Fn(&FileReport) -> io::Result<()> + Sync),
+    ) -> io::Result<()> {
+        match self.process_file_with_base(path, base_dir) {
+            Ok(mut report) => {
+                report.renamed_from =
+                    renamed_from.map(|old| transform::header_path(old, base_dir).into());
+                on_report(&report)
+            }
+            // Note: process_file increments skipped_count on specific internal errors/skips
+            Err(err) => {
+                eprintln!("Error processing {}: {err}", path.display());
+                Ok(())
+            }
+        }
+    }
+
+    fn walk_directory(
+        self: &Arc<Self>,
+        dir: &Path,
+        base_dir: PathBuf,
+        on_report: &(dyn Fn(&FileReport) -> io::Result<()> + Sync),
+    ) -> io::Result<()> {
+        // Build the walker. Hidden entries are filtered below, so that dotfiles with a file
+        // name rule (`.bashrc`) are still processed.
+        let mut builder = WalkBuilder::new(dir);
+        builder.hidden(false);
 
         if self.args.no_recursive {
             builder.max_depth(Some(1));
         }
 
+        if let Some(threads) = self.args.threads {
+            builder.threads(threads);
+        }
+
+        // The walker applies nested .gitignore files itself, with full gitignore semantics
+        if self.args.no_ignore_merge {
+            builder
+                .git_ignore(false)
+                .git_exclude(false)
+                .git_global(false);
+        }
+
         let cli = self.clone();
+        let filter_base_dir = base_dir.clone();
         builder.filter_entry(move |entry: &DirEntry| -> bool {
-            if entry.file_type().is_some_and(|ft| ft.is_dir()) {
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            if !cli.passes_glob_filters(entry.path(), &filter_base_dir, is_dir) {
+                return false;
+            }
+
+            // Hidden directories (`.git`, ...) are never walked into, even with --force, and
+            // hidden files need a file name rule. The directory given as target is always kept.
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if hidden && entry.depth() > 0 {
+                if is_dir {
+                    return false;
+                }
+                if cli
+                    .config
+                    .extension_styles
+                    .file_name_style(entry.path())
+                    .is_none()
+                {
+                    return false;
+                }
+            }
+
+            if is_dir {
                 // Use the cloned Arc inside the closure
                 let should_skip = cli.should_skip_directory(entry.path());
                 if should_skip {
-                    // println!("Skipping directory due to config: {}", entry.path().display()); // Optional debug noise
+                    cli.skipped_dirs
+                        .lock()
+                        .unwrap()
+                        .push(entry.path().to_path_buf());
                 }
                 !should_skip // Keep directory if it's NOT skipped by our custom logic
             } else {
-                true // Always keep files initially, standard filters and process_file will handle later
+                // Standard filters and process_file handle the rest
+                !cli.should_skip_file(entry.path())
             }
         });
 
-        // Process files in parallel
+        // Process files in parallel. The first reporting error stops every worker.
+        let report_error = Mutex::new(None);
         builder.build_parallel().run(|| {
             let cli = self.clone(); // Clone Arc for the worker closure
+            let base_dir = base_dir.clone();
+            let report_error = &report_error;
             Box::new(move |result| {
                 match result {
                     Ok(entry) => {
                         // Check if it's a file *after* filtering (standard filters might remove files)
-                        if entry.file_type().is_some_and(|ft| ft.is_file()) {
-                            if cli.should_process_file(entry.path()) {
-                                // Process the file if the extension matches
-                                if let Err(err) = cli.process_file(entry.path()) {
-                                    eprintln!("Error processing {}: {err}", entry.path().display());
-                                    // Note: process_file increments skipped_count on specific internal errors/skips
-                                }
-                            } else {
-                                // File doesn't match our extension list, count as skipped for summary
-                                cli.skipped_count.fetch_add(1, Ordering::Relaxed);
-                            }
+                        if entry.file_type().is_some_and(|ft| ft.is_file())
+                            && let Err(e) =
+                                cli.process_entry(entry.path(), &base_dir, None, on_report)
+                        {
+                            report_error.lock().unwrap().get_or_insert(e);
+                            return WalkState::Quit;
                         } // Ignore directories and other types here
                         WalkState::Continue
                     }
@@ -541,13 +1013,55 @@ impl Cli {
             })
         });
 
-        println!("\nSummary:");
-        let (processed, skipped) = self.get_stats();
-        println!("  Files processed: {processed}");
-        println!("  Files skipped: {skipped}");
+        let skipped_dirs = std::mem::take(&mut *self.skipped_dirs.lock().unwrap());
+        if let Some(e) = report_error.into_inner().unwrap() {
+            return Err(e);
+        }
+        for path in skipped_dirs {
+            on_report(&FileReport {
+                path: transform::header_path(&path, &base_dir).into(),
+                outcome: FileOutcome::Skipped(SkipReason::IgnoredDir),
+                renamed_from: None,
+            })?;
+        }
+        Ok(())
+    }
 
-        if self.args.dry_run {
-            println!("\nThis was a dry run. No files were modified.");
+    /// Prints one `<status>: <path>` line per failing file to stdout, and a summary to stderr
+    fn report_check_failures(&self) -> io::Result<i32> {
+        let failures = self.check_failures();
+        let mut out = io::stdout().lock();
+        for (path, status) in &failures {
+            writeln!(out, "{}: {path}", status.as_str())?;
+        }
+
+        if failures.is_empty() {
+            Ok(0)
+        } else {
+            eprintln!(
+                "{} file(s) missing or with a stale path comment",
+                failures.len()
+            );
+            Ok(1)
         }
     }
 }
+
+/// The exit code after failing to write the output. A closed pipe (`| head`) ends the run
+/// quietly, like it does for other command line tools.
+fn output_error_code(e: &io::Error) -> i32 {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        return 0;
+    }
+    eprintln!("Error writing output: {e}");
+    1
+}
+
+/// Writes the filtered content for stdin mode, returning the exit code
+fn write_stdout(bytes: &[u8]) -> i32 {
+    if let Err(e) = io::stdout().write_all(bytes) {
+        eprintln!("Error writing stdout: {e}");
+        return 1;
+    }
+    0
+}
@@ -1,21 +1,93 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path, sync::RwLock};
 
-use clap::ValueEnum;
+use globset::{GlobBuilder, GlobMatcher};
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Hash)]
+use crate::{encoding, placement, resolve, sniff};
+
+/// Config directive for custom comment styles, eg `style razor "@*" "*@"`
+pub const STYLE_DIRECTIVE: &str = "style";
+
+/// Config directive for exact file names, eg `name Makefile #`
+pub const NAME_DIRECTIVE: &str = "name";
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub enum Style {
-    Slash,      // //
-    SlashStar,  // /* */
-    Hash,       // #
-    Semi,       // ;
-    Xml,        // <!-- -->
-    DoubleDash, // --
-    Percent,    // %
+    /// `//`
+    Slash,
+    /// `/* */`
+    SlashStar,
+    /// `#`
+    Hash,
+    /// `;`
+    Semi,
+    /// `<!-- -->`
+    Xml,
+    /// `--`
+    DoubleDash,
+    /// `%`
+    Percent,
+    /// `(* *)`, for OCaml, F# and Pascal
+    ParenStar,
+    /// `{- -}`, for Haskell and Elm
+    BraceDash,
+    /// `REM`, for Batch
+    Rem,
+    /// `::`, for Batch
+    DoubleColon,
+    /// `'`, for Visual Basic
+    Apostrophe,
+    /// `!`, for Fortran
+    Bang,
+    /// `"`, for Vim script
+    Quote,
+    /// `%%`, for Erlang and MATLAB
+    DoublePercent,
+    /// `{# #}`, for Jinja and Twig
+    BraceHash,
+    /// `{{! }}`, for Handlebars and Mustache
+    Handlebars,
+    /// `<%# %>`, for ERB
+    Erb,
+    /// `@* *@`, for Razor
+    Razor,
+    /// `""" """`, a Python docstring
+    Docstring,
+    /// A style defined by a `style` rule in a config file
+    Custom(CustomId),
 }
 
+/// Identifies a custom style. Only [`define_style`] hands them out, so every id refers to a
+/// defined style.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct CustomId(u32);
+
 impl Style {
+    /// The built-in styles, every style but [`Style::Custom`]
+    pub const BUILTIN: [Style; 20] = [
+        Style::Slash,
+        Style::SlashStar,
+        Style::Hash,
+        Style::Semi,
+        Style::Xml,
+        Style::DoubleDash,
+        Style::Percent,
+        Style::ParenStar,
+        Style::BraceDash,
+        Style::Rem,
+        Style::DoubleColon,
+        Style::Apostrophe,
+        Style::Bang,
+        Style::Quote,
+        Style::DoublePercent,
+        Style::BraceHash,
+        Style::Handlebars,
+        Style::Erb,
+        Style::Razor,
+        Style::Docstring,
+    ];
+
     fn from_str(s: &str) -> Option<Self> {
         match s.trim() {
             "//" => Some(Style::Slash),
@@ -25,12 +97,68 @@ impl Style {
             "<!-- -->" => Some(Style::Xml),
             "--" => Some(Style::DoubleDash),
             "%" => Some(Style::Percent),
+            "(* *)" => Some(Style::ParenStar),
+            "{- -}" => Some(Style::BraceDash),
+            "REM" | "rem" => Some(Style::Rem),
+            "::" => Some(Style::DoubleColon),
+            "'" => Some(Style::Apostrophe),
+            "!" => Some(Style::Bang),
+            "\"" => Some(Style::Quote),
+            "%%" => Some(Style::DoublePercent),
+            "{# #}" => Some(Style::BraceHash),
+            "{{! }}" => Some(Style::Handlebars),
+            "<%# %>" => Some(Style::Erb),
+            "@* *@" => Some(Style::Razor),
+            "\"\"\" \"\"\"" => Some(Style::Docstring),
             _ => None,
         }
     }
+
+    /// Parses a built-in style given either by its delimiters (`//`, `/* */`, ...) like in the
+    /// config file, or by its name (`slash`, `slash-star`, ...) like on the command line.
+    /// Custom styles are looked up with `CommentConfig::parse_style` from the loaded config.
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::from_str(s).or_else(|| {
+            Self::BUILTIN
+                .into_iter()
+                .find(|style| style.name().eq_ignore_ascii_case(s.trim()))
+        })
+    }
+
+    /// The name of the style, as accepted by `--comment-style`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Style::Custom(id) => custom_style(*id).name,
+            Style::Slash => "slash",
+            Style::SlashStar => "slash-star",
+            Style::Hash => "hash",
+            Style::Semi => "semi",
+            Style::Xml => "xml",
+            Style::DoubleDash => "double-dash",
+            Style::Percent => "percent",
+            Style::ParenStar => "paren-star",
+            Style::BraceDash => "brace-dash",
+            Style::Rem => "rem",
+            Style::DoubleColon => "double-colon",
+            Style::Apostrophe => "apostrophe",
+            Style::Bang => "bang",
+            Style::Quote => "quote",
+            Style::DoublePercent => "double-percent",
+            Style::BraceHash => "brace-hash",
+            Style::Handlebars => "handlebars",
+            Style::Erb => "erb",
+            Style::Razor => "razor",
+            Style::Docstring => "docstring",
+        }
+    }
+
     // Method to get the comment delimiters
     pub fn delimiters(&self) -> (&'static str, &'static str) {
         match self {
+            Style::Custom(id) => {
+                let custom = custom_style(*id);
+                (custom.start, custom.end)
+            }
             Style::Slash => ("// ", ""),
             Style::SlashStar => ("/* ", " */"),
             Style::Hash => ("# ", ""),
@@ -38,68 +166,282 @@ impl Style {
             Style::Xml => ("<!-- ", " -->"),
             Style::DoubleDash => ("-- ", ""),
             Style::Percent => ("% ", ""),
+            Style::ParenStar => ("(* ", " *)"),
+            Style::BraceDash => ("{- ", " -}"),
+            Style::Rem => ("REM ", ""),
+            Style::DoubleColon => (":: ", ""),
+            Style::Apostrophe => ("' ", ""),
+            Style::Bang => ("! ", ""),
+            Style::Quote => ("\" ", ""),
+            Style::DoublePercent => ("%% ", ""),
+            Style::BraceHash => ("{# ", " #}"),
+            Style::Handlebars => ("{{! ", " }}"),
+            Style::Erb => ("<%# ", " %>"),
+            Style::Razor => ("@* ", " *@"),
+            Style::Docstring => ("\"\"\"", "\"\"\""),
         }
     }
-}
 
-// Comment style delimiters - adjusted to use delimiters() method where possible
-pub static DELIMITERS: Lazy<HashMap<Style, (String, String)>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-    for style in [
-        Style::Slash,
-        Style::SlashStar,
-        Style::Hash,
-        Style::Semi,
-        Style::Xml,
-        Style::DoubleDash,
-        Style::Percent,
-    ] {
-        let (start, end) = style.delimiters();
-        map.insert(style, (start.to_string(), end.to_string()));
+    /// The regex matching a path comment in this style
+    pub fn regex(&self) -> &'static Regex {
+        match self {
+            Style::Custom(id) => &custom_style(*id).regex,
+            _ => &REGEXES[self],
+        }
     }
-    map
+}
+
+/// A comment style defined in a config file
+#[derive(Debug)]
+struct CustomStyle {
+    name: &'static str,
+    start: &'static str,
+    end: &'static str,
+    regex: Regex,
+}
+
+/// The custom styles defined so far, indexed by their [`Style::Custom`] id. They are leaked so
+/// that styles stay `Copy` and their delimiters `'static` like the built-in ones.
+static CUSTOM_STYLES: Lazy<RwLock<Vec<&'static CustomStyle>>> =
+    Lazy::new(|| RwLock::new(Vec::new()));
+
+fn custom_style(id: CustomId) -> &'static CustomStyle {
+    CUSTOM_STYLES.read().unwrap()[id.0 as usize]
+}
+
+/// Defines a custom style from its delimiters, eg `"@*"` and `"*@"`, or `"//"` and `""` for a
+/// line comment. Defining the same style again returns the same [`Style`].
+pub fn define_style(name: &str, start: &str, end: &str) -> Style {
+    // Pad the delimiters with a space like the built-in styles
+    let start = format!("{} ", start.trim());
+    let end = match end.trim() {
+        "" => String::new(),
+        end => format!(" {end}"),
+    };
+
+    let mut styles = CUSTOM_STYLES.write().unwrap();
+    let existing = styles
+        .iter()
+        .position(|custom| custom.name == name && custom.start == start && custom.end == end);
+    let id = existing.unwrap_or_else(|| {
+        let regex = path_comment_regex(&start, &end);
+        styles.push(Box::leak(Box::new(CustomStyle {
+            name: String::leak(name.to_string()),
+            start: String::leak(start),
+            end: String::leak(end),
+            regex,
+        })));
+        styles.len() - 1
+    });
+    Style::Custom(CustomId(id as u32))
+}
+
+/// Regex for something that looks like a file path in a path comment
+pub const PATH_PATTERN: &str =
+    r"(?:/|\\|[A-Za-z]:)?(?:[\w\-\.]+(?:/|\\))+[\w\-\.]+(?:\.\w+)?|[\w\-\.]+\.\w+";
+
+/// The regex matching a path comment between the `start` and `end` delimiters
+fn path_comment_regex(start: &str, end: &str) -> Regex {
+    let pattern = format!(
+        r"^({start_esc})\s*({PATH_PATTERN})\s*({end_esc})$",
+        start_esc = regex::escape(start),
+        end_esc = regex::escape(end)
+    );
+    // The delimiters are escaped, so this can only fail if PATH_PATTERN is broken
+    Regex::new(&pattern).expect("path comment regex should compile")
+}
+
+// Pre-baked regexes for each built-in comment style, custom ones are built by `define_style`
+static REGEXES: Lazy<HashMap<Style, Regex>> = Lazy::new(|| {
+    Style::BUILTIN
+        .into_iter()
+        .map(|style| {
+            let (start, end) = style.delimiters();
+            (style, path_comment_regex(start, end))
+        })
+        .collect()
 });
 
-// Pre-baked regexes for each comment style
-pub static REGEXES: Lazy<HashMap<Style, Regex>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-    for style in [
-        Style::Slash,
-        Style::SlashStar,
-        Style::Hash,
-        Style::Semi,
-        Style::Xml,
-        Style::DoubleDash,
-        Style::Percent,
-    ] {
-        let (start, end) = style.delimiters();
-        let pattern = format!(
-            r"^({start_esc})\s*((?:/|\\|[A-Za-z]:)?(?:[\w\-\.]+(?:/|\\))+[\w\-\.]+(?:\.\w+)?|[\w\-\.]+\.\w+)\s*({end_esc})$",
-            start_esc = regex::escape(start),
-            end_esc = regex::escape(end)
-        );
-
-        // Using expect is acceptable in static initialization since it will fail at startup
-        // if there's an issue with the regex patterns
-        map.insert(
-            style,
-            Regex::new(&pattern)
-                .unwrap_or_else(|_| panic!("Failed to compile regex pattern for {style:?} style")),
-        );
+/// Comment styles by extension, plus file name rules for files an extension doesn't describe
+/// (`Dockerfile`, `*.d.ts`, ...).
+///
+/// Lookup order: the exact file name, then the file name globs with later ones winning, then
+/// the extension.
+#[derive(Clone, Debug, Default)]
+pub struct CommentConfig {
+    /// Keyed by lowercase extension
+    pub extensions: HashMap<String, Style>,
+    /// Keyed by lowercase file name, eg `makefile` or `.bashrc`
+    pub filenames_exact: HashMap<String, Style>,
+    /// Globs matched against the file name
+    pub filenames: Vec<FilenameRule>,
+    /// Custom styles defined with `style` rules, by name
+    pub custom_styles: HashMap<String, Style>,
+}
+
+/// A glob matched against the file name, eg `Dockerfile*` or `*.d.ts`
+#[derive(Clone, Debug)]
+pub struct FilenameRule {
+    pub pattern: String,
+    pub matcher: GlobMatcher,
+    pub style: Style,
+}
+
+impl CommentConfig {
+    /// Adds a rule for `key`: a file name glob if it contains glob syntax, an exact file name
+    /// if it has a dot after its first character (`CMakeLists.txt`), or an extension
+    /// otherwise, with any leading dot dropped (`rs`, `.rs`, `R`). An invalid glob is skipped
+    /// with a warning.
+    pub fn insert(&mut self, key: &str, style: Style, warnings: &mut Vec<String>) {
+        if !key.contains(['*', '?', '[', '{']) {
+            if key.get(1..).is_some_and(|rest| rest.contains('.')) {
+                self.insert_name(key, style);
+            } else {
+                let extension = key.strip_prefix('.').unwrap_or(key);
+                self.extensions.insert(extension.to_lowercase(), style);
+            }
+            return;
+        }
+        // File names are matched case-insensitively, like extensions
+        match GlobBuilder::new(key).case_insensitive(true).build() {
+            Ok(glob) => {
+                // A later rule for the same pattern replaces the earlier one
+                self.filenames.retain(|rule| rule.pattern != key);
+                self.filenames.push(FilenameRule {
+                    pattern: key.to_string(),
+                    matcher: glob.compile_matcher(),
+                    style,
+                });
+            }
+            Err(e) => warnings.push(format!("Invalid file name glob '{key}': {e}, skipping")),
+        }
     }
 
-    map
-});
+    /// Adds a rule for the exact file name `name`, eg `Makefile` or `.bashrc`
+    pub fn insert_name(&mut self, name: &str, style: Style) {
+        self.filenames_exact.insert(name.to_lowercase(), style);
+    }
+
+    /// The style configured for the file at `path`, if any
+    pub fn style_for(&self, path: &Path) -> Option<Style> {
+        if let Some(style) = self.file_name_style(path) {
+            return Some(style);
+        }
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        self.extensions.get(&extension).copied()
+    }
+
+    /// The style of an exact file name or file name glob rule matching `path`, if any
+    pub fn file_name_style(&self, path: &Path) -> Option<Style> {
+        let file_name = path.file_name()?.to_string_lossy().to_lowercase();
+        if let Some(&style) = self.filenames_exact.get(&file_name) {
+            return Some(style);
+        }
+        self.filenames
+            .iter()
+            .rev()
+            .find(|rule| rule.matcher.is_match(&file_name))
+            .map(|rule| rule.style)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty() && self.filenames_exact.is_empty() && self.filenames.is_empty()
+    }
+
+    /// Parses a style given by its delimiters, or by the name of a built-in or custom style
+    pub fn parse_style(&self, s: &str) -> Option<Style> {
+        Style::parse(s).or_else(|| self.custom_styles.get(s.trim()).copied())
+    }
+
+    /// Defines the custom style of a `style` rule, eg `style razor "@*" "*@"`
+    fn add_custom_style(&mut self, line: &str, warnings: &mut Vec<String>) {
+        let Some(words) = split_quoted(line) else {
+            warnings.push(format!(
+                "Unterminated quote in style rule '{line}' in config file, skipping"
+            ));
+            return;
+        };
+        let (name, start, end) = match words.as_slice() {
+            [_, name, start] => (name, start, ""),
+            [_, name, start, end] => (name, start, end.as_str()),
+            _ => {
+                warnings.push(format!(
+                    "Invalid style rule '{line}' in config file, skipping"
+                ));
+                return;
+            }
+        };
+
+        if !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            warnings.push(format!(
+                "Invalid style name '{name}' in config file, use letters, digits, '-' and '_', skipping"
+            ));
+        } else if Style::parse(name).is_some() {
+            warnings.push(format!(
+                "Style name '{name}' in config file is a built-in style, skipping"
+            ));
+        } else if start.trim().is_empty() {
+            warnings.push(format!(
+                "Style '{name}' in config file has no start delimiter, skipping"
+            ));
+        } else {
+            let style = define_style(name, start, end);
+            self.custom_styles.insert(name.clone(), style);
+        }
+    }
+}
+
+/// Splits a line into whitespace separated words, where `"..."` quotes a word that may be
+/// empty or contain spaces, with `\"` and `\\` escapes. Returns `None` on an unterminated quote.
+fn split_quoted(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => word.push(chars.next()?),
+                    c => word.push(c),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+            words.push(word);
+        }
+    }
+    Some(words)
+}
 
-pub type CommentConfig = HashMap<String, Style>;
 // Default configuration string with common file extensions and their comment styles
-const DEFAULT_CONFIG: &str = include_str!("comments.cfg");
+pub(crate) const DEFAULT_CONFIG: &str = include_str!("comments.cfg");
 pub fn default_config() -> CommentConfig {
-    parse_config(DEFAULT_CONFIG)
+    parse_config(DEFAULT_CONFIG, &mut Vec::new())
 }
 
-pub fn parse_config(content: &str) -> CommentConfig {
-    let mut extension_styles = HashMap::new();
+/// Parses the extension and file name rules of a config file, adding a warning to `warnings`
+/// for each rule it skips
+pub fn parse_config(content: &str, warnings: &mut Vec<String>) -> CommentConfig {
+    let mut extension_styles = CommentConfig::default();
+
+    // Custom styles can be used before the rule defining them
+    for line in content.lines() {
+        let line = line.trim();
+        if line.split_whitespace().next() == Some(STYLE_DIRECTIVE) {
+            extension_styles.add_custom_style(line, warnings);
+        }
+    }
 
     for line in content.lines() {
         let line = line.trim();
@@ -111,24 +453,49 @@ pub fn parse_config(content: &str) -> CommentConfig {
 
         // Split line into extension and comment style
         let parts: Vec<&str> = line.split_whitespace().collect();
+
+        // Preamble, encoding, interpreter and resolve rules share the file, they are parsed by
+        // their own modules
+        if [
+            STYLE_DIRECTIVE,
+            placement::PREAMBLE_DIRECTIVE,
+            encoding::ENCODING_DIRECTIVE,
+            sniff::INTERPRETER_DIRECTIVE,
+            resolve::RESOLVE_DIRECTIVE,
+        ]
+        .contains(&parts[0])
+        {
+            continue;
+        }
+
+        // Exact file names are marked by the directive, the other keys by their shape
+        let (is_name, parts) = match parts.split_first() {
+            Some((&NAME_DIRECTIVE, rest)) if !rest.is_empty() => (true, rest),
+            _ => (false, &parts[..]),
+        };
+
         if parts.len() >= 2 {
-            // Extension is always the first part, remove leading dot if present
-            let extension = parts[0].trim_start_matches('.').to_lowercase();
+            // The extension, file name or file name glob is always the first part
+            let key = parts[0];
             let style_str = parts[1..].join(" ");
 
-            if let Some(style) = Style::from_str(&style_str) {
-                extension_styles.insert(extension, style);
+            if let Some(style) = extension_styles.parse_style(&style_str) {
+                if is_name {
+                    extension_styles.insert_name(key, style);
+                } else {
+                    extension_styles.insert(key, style, warnings);
+                }
             } else {
-                eprintln!(
-                    "Warning: Unknown comment style '{}' for extension '.{}' in config file, skipping",
-                    style_str, extension
-                );
+                warnings.push(format!(
+                    "Unknown comment style '{}' for '{}' in config file, skipping",
+                    style_str, key
+                ));
             }
         } else if parts.len() == 1 {
-            eprintln!(
-                "Warning: Missing comment style for extension '.{}' in config file, skipping",
+            warnings.push(format!(
+                "Missing comment style for '{}' in config file, skipping",
                 parts[0]
-            );
+            ));
         }
     }
 
@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use encoding_rs::Encoding;
+
+use crate::{comments, encoding::EncodingConfig, placement, resolve, sniff, transform};
+
+/// Comment styles and placement rules used to decide what header a file gets
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub extension_styles: comments::CommentConfig,
+    pub preambles: placement::PreambleConfig,
+    pub encodings: EncodingConfig,
+    pub interpreters: sniff::InterpreterConfig,
+    pub resolvers: resolve::ResolveConfig,
+    /// Problems found while parsing, one per skipped rule. Printing them is up to the caller.
+    pub warnings: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            extension_styles: comments::default_config(),
+            preambles: placement::default_preambles(),
+            encodings: EncodingConfig::default(),
+            interpreters: sniff::default_interpreters(),
+            resolvers: resolve::default_rules(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Parses a config file. Its extension styles replace the defaults, while its preamble
+    /// rules are added to the defaults so a custom config can't accidentally produce broken
+    /// XML or PHP files. Its interpreter and resolve rules are added to the defaults too.
+    ///
+    /// Invalid rules are skipped and described in [`Config::warnings`].
+    pub fn parse(content: &str) -> Self {
+        let mut warnings = Vec::new();
+        let mut preambles = placement::default_preambles();
+        placement::merge_preambles(&mut preambles, content, &mut warnings);
+        let mut encodings = EncodingConfig::default();
+        encodings.merge(content, &mut warnings);
+        let extension_styles = comments::parse_config(content, &mut warnings);
+        let mut interpreters = sniff::default_interpreters();
+        sniff::merge_interpreters(&mut interpreters, content, &extension_styles, &mut warnings);
+        let mut resolvers = resolve::default_rules();
+        resolve::merge_rules(&mut resolvers, content, &extension_styles, &mut warnings);
+        Self {
+            extension_styles,
+            preambles,
+            encodings,
+            interpreters,
+            resolvers,
+            warnings,
+        }
+    }
+
+    /// Returns the comment style configured for the file at `path`, if any
+    pub fn style_for(&self, path: &Path) -> Option<comments::Style> {
+        self.extension_styles.style_for(path)
+    }
+
+    /// Detects the comment style of a file from its shebang or modeline, for files
+    /// [`Config::style_for`] doesn't cover
+    pub fn sniff_style(&self, content: &str) -> Option<comments::Style> {
+        sniff::detect(content, &self.interpreters)
+    }
+
+    /// Picks the style and header position of a multi-language file (`.vue`, `.php`, ...) from
+    /// its opening line, given the `style` found from its path
+    pub fn resolve(
+        &self,
+        path: &Path,
+        content: &str,
+        style: comments::Style,
+    ) -> resolve::Resolution<'_> {
+        let rules = resolve::rules_for(&self.resolvers, extension_of(path).as_deref());
+        resolve::resolve(&rules, content, style)
+    }
+
+    /// Returns the preamble rules that apply to the file at `path`
+    pub fn preambles_for(&self, path: &Path) -> Vec<&placement::Preamble> {
+        placement::rules_for(&self.preambles, extension_of(path).as_deref())
+    }
+
+    /// Returns the encoding configured for the file at `path` (relative to `base_dir`), if any.
+    /// A byte order mark in the file still takes precedence.
+    pub fn encoding_for(&self, path: &Path, base_dir: &Path) -> Option<&'static Encoding> {
+        let rel_path = transform::header_path(path, base_dir);
+        self.encodings.get(&rel_path, extension_of(path).as_deref())
+    }
+
+    /// Applies the header for `path` (made relative to `base_dir`) to `content`.
+    /// Returns `None` if no comment style is configured for the file, or if its style is
+    /// `docstring` and it already has one (a docstring header would replace it).
+    pub fn apply(
+        &self,
+        content: &str,
+        path: &Path,
+        base_dir: &Path,
+        options: transform::HeaderOptions,
+    ) -> Option<transform::HeaderUpdate> {
+        let style = self.style_for(path)?;
+        let resolution = self.resolve(path, content, style);
+        let preambles = self.preambles_for(path);
+        if resolution.style == comments::Style::Docstring
+            && transform::has_docstring(content, &preambles)
+        {
+            return None;
+        }
+        let rel_path = transform::header_path(path, base_dir);
+        Some(transform::apply_header(
+            content,
+            &rel_path,
+            resolution.style,
+            &preambles,
+            resolution.options(options),
+        ))
+    }
+}
+
+/// Lowercase extension of `path`, the key used by the extension config
+pub(crate) fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
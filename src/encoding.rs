@@ -0,0 +1,155 @@
+use std::{collections::HashMap, io};
+
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Config directive for file encodings, eg `encoding utf-16le cs xml` or `encoding latin1 legacy/**/*.sql`
+pub const ENCODING_DIRECTIVE: &str = "encoding";
+
+/// Encodings configured for files without a byte order mark
+#[derive(Clone, Debug, Default)]
+pub struct EncodingConfig {
+    /// Keyed by lowercase extension
+    extensions: HashMap<String, &'static Encoding>,
+    /// Glob rules, checked before extensions
+    globs: Vec<GlobRule>,
+}
+
+/// A glob matched against the path relative to the base directory, or just against the file
+/// name if it has no `/` (like in a `.gitignore`)
+#[derive(Clone, Debug)]
+struct GlobRule {
+    matcher: GlobMatcher,
+    file_name_only: bool,
+    encoding: &'static Encoding,
+}
+
+impl EncodingConfig {
+    /// Adds the `encoding` directives found in `content`, ignoring all other lines.
+    ///
+    /// Format:
+    ///   encoding <label> <extension or glob>...
+    ///
+    /// Labels are the WHATWG ones, eg `utf-16le`, `latin1`, `shift_jis`. Skipped rules are
+    /// reported in `warnings`.
+    pub fn merge(&mut self, content: &str, warnings: &mut Vec<String>) {
+        for line in content.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.first() != Some(&ENCODING_DIRECTIVE) {
+                continue;
+            }
+
+            let [_, label, targets @ ..] = parts.as_slice() else {
+                warnings.push(format!(
+                    "Incomplete encoding rule '{}' in config file, skipping",
+                    line.trim()
+                ));
+                continue;
+            };
+            if targets.is_empty() {
+                warnings.push(format!(
+                    "Incomplete encoding rule '{}' in config file, skipping",
+                    line.trim()
+                ));
+                continue;
+            }
+            let Some(encoding) = Encoding::for_label(label.as_bytes()) else {
+                warnings.push(format!(
+                    "Unknown encoding '{label}' in config file, skipping"
+                ));
+                continue;
+            };
+
+            for target in targets {
+                self.add(target, encoding, warnings);
+            }
+        }
+    }
+
+    /// Adds a rule for an extension, or for a glob if `target` contains `/` or glob syntax
+    pub fn add(&mut self, target: &str, encoding: &'static Encoding, warnings: &mut Vec<String>) {
+        if !target.contains(['/', '*', '?', '[', '{']) {
+            let extension = target.trim_start_matches('.').to_lowercase();
+            self.extensions.insert(extension, encoding);
+            return;
+        }
+        match GlobBuilder::new(target).literal_separator(true).build() {
+            Ok(glob) => self.globs.push(GlobRule {
+                matcher: glob.compile_matcher(),
+                file_name_only: !target.contains('/'),
+                encoding,
+            }),
+            Err(e) => warnings.push(format!("Invalid encoding glob '{target}': {e}, skipping")),
+        }
+    }
+
+    /// The encoding configured for a file, given its path relative to the base directory
+    /// and its lowercase extension. Later glob rules win over earlier ones.
+    pub fn get(&self, rel_path: &str, extension: Option<&str>) -> Option<&'static Encoding> {
+        let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        self.globs
+            .iter()
+            .rev()
+            .find(|rule| {
+                let target = if rule.file_name_only {
+                    file_name
+                } else {
+                    rel_path
+                };
+                rule.matcher.is_match(target)
+            })
+            .map(|rule| rule.encoding)
+            .or_else(|| extension.and_then(|ext| self.extensions.get(ext).copied()))
+    }
+}
+
+/// File content decoded to text, remembering how to encode it back
+#[derive(Debug)]
+pub struct Decoded {
+    /// The text, including the byte order mark as a leading `\u{feff}` if the file has one
+    pub text: String,
+    pub encoding: &'static Encoding,
+}
+
+/// The encoding of a file starting with `bytes`: from its byte order mark, else the
+/// `configured` one, else UTF-8
+pub fn detect(bytes: &[u8], configured: Option<&'static Encoding>) -> &'static Encoding {
+    Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or(configured)
+        .unwrap_or(UTF_8)
+}
+
+/// Decodes `bytes`. A byte order mark wins over the `configured` encoding, which wins over UTF-8.
+/// Returns `None` if the bytes aren't valid in that encoding, so they couldn't be written back
+/// unchanged.
+pub fn decode(bytes: &[u8], configured: Option<&'static Encoding>) -> Option<Decoded> {
+    let encoding = detect(bytes, configured);
+    // The BOM is kept in the text, it's written back as part of it
+    let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+    Some(Decoded {
+        text: text.into_owned(),
+        encoding,
+    })
+}
+
+/// Encodes `text` back to `encoding`. Fails if it contains characters the encoding can't
+/// represent, eg a non-ASCII path in a header.
+pub fn encode(text: &str, encoding: &'static Encoding) -> io::Result<Vec<u8>> {
+    // encoding_rs only decodes UTF-16 (the web never encodes to it), so it's done by hand here
+    if encoding == UTF_16LE {
+        return Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect());
+    }
+    if encoding == UTF_16BE {
+        return Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect());
+    }
+
+    let (bytes, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("content can't be represented in {}", encoding.name()),
+        ));
+    }
+    Ok(bytes.into_owned())
+}
@@ -0,0 +1,100 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Which files to take from git instead of walking the filesystem
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GitSelector {
+    /// Files staged in the index
+    Staged,
+    /// Files changed in the worktree compared to a revision, staged or not
+    Changed(String),
+    /// Every file tracked by git
+    Tracked,
+    /// Files renamed in the index, or in a revision range when given
+    Renamed(Option<String>),
+}
+
+/// A file selected from git
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GitFile {
+    pub path: PathBuf,
+    /// The path the file had before, for renames
+    pub renamed_from: Option<PathBuf>,
+}
+
+impl GitSelector {
+    /// The command line flag selecting this, for messages
+    pub fn flag(&self) -> &'static str {
+        match self {
+            GitSelector::Staged => "--git-staged",
+            GitSelector::Changed(_) => "--git-changed",
+            GitSelector::Tracked => "--git-tracked",
+            GitSelector::Renamed(_) => "--git-renamed",
+        }
+    }
+}
+
+/// Lists the selected files of the repository at `repo_root` by calling `git`.
+/// Deleted files are left out, the returned paths are absolute if `repo_root` is.
+pub fn list_files(repo_root: &Path, selector: &GitSelector) -> io::Result<Vec<GitFile>> {
+    let mut command = Command::new("git");
+    command.current_dir(repo_root);
+    match selector {
+        GitSelector::Staged => command.args([
+            "diff",
+            "--cached",
+            "--name-only",
+            "--diff-filter=ACMR",
+            "-z",
+        ]),
+        GitSelector::Changed(rev) => command
+            .args(["diff", "--name-only", "--diff-filter=ACMR", "-z"])
+            .arg(rev)
+            .arg("--"),
+        GitSelector::Tracked => command.args(["ls-files", "--cached", "-z"]),
+        GitSelector::Renamed(range) => {
+            command.args(["diff", "--name-status", "-M", "--diff-filter=R", "-z"]);
+            match range {
+                Some(range) => command.arg(range).arg("--"),
+                None => command.arg("--cached"),
+            }
+        }
+    };
+
+    let output = command
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run git: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("git failed: {}", stderr.trim())));
+    }
+
+    // Paths are NUL separated and relative to the repository root
+    let mut fields = output
+        .stdout
+        .split(|&byte| byte == 0)
+        .filter(|field| !field.is_empty())
+        .map(|field| String::from_utf8_lossy(field).into_owned());
+
+    let mut files = Vec::new();
+    if let GitSelector::Renamed(_) = selector {
+        // Each rename is a `R<similarity>` status followed by the old and the new path
+        while let (Some(_status), Some(old), Some(new)) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            files.push(GitFile {
+                path: repo_root.join(new),
+                renamed_from: Some(repo_root.join(old)),
+            });
+        }
+    } else {
+        files.extend(fields.map(|path| GitFile {
+            path: repo_root.join(path),
+            renamed_from: None,
+        }));
+    }
+    Ok(files)
+}
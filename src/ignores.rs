@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+
+/// Project ignore file for paths that should never get a header, with gitignore syntax.
+/// Like `.gitignore`, one can be placed in any directory.
+pub const IGNORE_FILENAME: &str = ".pathcommentignore";
+
+/// The result of checking a path against the `.pathcommentignore` files
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IgnoreMatch {
+    /// No rule matched
+    None,
+    /// The path is ignored
+    Ignore,
+    /// The path is explicitly re-included with a `!pattern`
+    Whitelist,
+}
+
+/// Matches paths against the `.pathcommentignore` files between the base directory and the path.
+/// Files are loaded lazily and cached per directory, so this can be shared between walker threads.
+pub struct IgnoreFiles {
+    base_dir: PathBuf,
+    cache: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+    /// Rules from the `ignore` patterns of the project config files, nearest first
+    project_rules: Vec<Gitignore>,
+}
+
+impl IgnoreFiles {
+    pub fn new(base_dir: &Path) -> Self {
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            cache: Mutex::new(HashMap::new()),
+            project_rules: Vec::new(),
+        }
+    }
+
+    /// Adds the `ignore` patterns of the project config files, which are checked after the
+    /// `.pathcommentignore` files
+    pub fn with_project_rules(mut self, rules: Vec<Gitignore>) -> Self {
+        self.project_rules = rules;
+        self
+    }
+
+    /// Checks `path` against every applicable `.pathcommentignore`. Deeper files take precedence
+    /// over the ones closer to the base directory, like nested `.gitignore` files. The `ignore`
+    /// patterns of the project config files come last.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> IgnoreMatch {
+        let path = self.normalize(path);
+
+        // Only directories up to the base directory are consulted, or just the parent directory
+        // when the path lives outside of it
+        let dirs: Vec<&Path> = if path.starts_with(&self.base_dir) {
+            path.ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(&self.base_dir))
+                .collect()
+        } else {
+            path.parent().into_iter().collect()
+        };
+
+        let ignore_files: Vec<Arc<Gitignore>> =
+            dirs.into_iter().filter_map(|dir| self.load(dir)).collect();
+        let project_rules = self
+            .project_rules
+            .iter()
+            // Matching a path outside of the rules' directory panics
+            .filter(|rules| path.starts_with(rules.path()));
+        for gitignore in ignore_files.iter().map(Arc::as_ref).chain(project_rules) {
+            match gitignore.matched_path_or_any_parents(&path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return IgnoreMatch::Ignore,
+                Match::Whitelist(_) => return IgnoreMatch::Whitelist,
+            }
+        }
+        IgnoreMatch::None
+    }
+
+    /// Makes `path` absolute so it can be compared with the (canonical) base directory
+    fn normalize(&self, path: &Path) -> PathBuf {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        if absolute.starts_with(&self.base_dir) {
+            return absolute;
+        }
+        // The base directory is canonical, so symlinks in the given path may hide that it's under it
+        path.canonicalize().unwrap_or(absolute)
+    }
+
+    fn load(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut cache = self.cache.lock().unwrap();
+        cache
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let file = dir.join(IGNORE_FILENAME);
+                if !file.is_file() {
+                    return None;
+                }
+                let mut builder = GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(&file) {
+                    eprintln!("Warning: Could not fully read {}: {e}", file.display());
+                }
+                builder
+                    .build()
+                    .inspect_err(|e| {
+                        eprintln!("Warning: Invalid ignore rules in {}: {e}", file.display())
+                    })
+                    .ok()
+                    .map(Arc::new)
+            })
+            .clone()
+    }
+}
+
+/// `--include`/`--exclude` globs, matched against paths relative to the base directory
+pub struct GlobFilters {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl GlobFilters {
+    /// Builds the filters, skipping invalid globs with a warning added to `warnings`
+    pub fn new(include: &[String], exclude: &[String], warnings: &mut Vec<String>) -> Self {
+        Self {
+            include: (!include.is_empty()).then(|| build_glob_set(include, warnings)),
+            exclude: build_glob_set(exclude, warnings),
+        }
+    }
+
+    /// Whether a directory should be walked, given its path relative to the base directory
+    pub fn keep_dir(&self, rel_path: &str) -> bool {
+        !self.exclude.is_match(rel_path)
+    }
+
+    /// Whether a file should be processed, given its path relative to the base directory.
+    /// Excluding a directory excludes every file under it.
+    pub fn keep_file(&self, rel_path: &str) -> bool {
+        let excluded = std::iter::once(rel_path)
+            .chain(rel_path.match_indices('/').map(|(i, _)| &rel_path[..i]))
+            .any(|path| self.exclude.is_match(path));
+        !excluded
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|set| set.is_match(rel_path))
+    }
+}
+
+fn build_glob_set(patterns: &[String], warnings: &mut Vec<String>) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // `*` stays within a path component, `**` crosses them, like in .gitignore
+        match GlobBuilder::new(pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => warnings.push(format!("Invalid glob '{pattern}': {e}, skipping")),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        warnings.push(format!("Could not build glob set: {e}"));
+        GlobSet::empty()
+    })
+}
@@ -0,0 +1,57 @@
+//! Prepend file paths as comments to source code files.
+//!
+//! The `path-comment` binary is a thin wrapper around this crate. The main entry points are:
+//!
+//! - [`apply_header`]: apply a header to some content, without touching the disk
+//! - [`Config`]: comment style lookup and config file parsing
+//! - [`Cli`]: walk files and directories, returning a [`FileReport`] per file. It's configured
+//!   with [`Args`], which library callers can build from `Args::default()`.
+//!
+//! Nothing is printed to stdout except by [`Cli::run`], the command line entry point. Problems
+//! with the configuration are collected instead of printed, see [`Config::warnings`] and
+//! [`Cli::warnings`].
+
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod test;
+
+mod args;
+mod cli;
+mod comments;
+mod config;
+mod encoding;
+mod git;
+mod ignores;
+mod placement;
+mod project;
+mod report;
+mod reporter;
+mod resolve;
+mod sniff;
+mod transform;
+mod write;
+
+pub use args::{Args, ColorChoice, OutputFormat, SortChoice};
+pub use cli::{BaseDir, Cli, resolve_base_dir};
+pub use comments::Style;
+pub use config::Config;
+pub use placement::Preamble;
+pub use project::{PROJECT_CONFIG_FILENAME, apply as apply_project_config};
+pub use report::{FileOutcome, FileReport, SkipReason};
+pub use transform::{HeaderAction, HeaderOptions, HeaderTemplate, HeaderUpdate, apply_header};
+
+/// Searches upwards from the `start_dir` for a directory containing `.git`.
+/// Returns the path to the directory containing `.git` if found, otherwise None.
+pub fn find_git_root(start_dir: &Path) -> Option<PathBuf> {
+    let mut current = start_dir.to_path_buf();
+    loop {
+        if current.join(".git").is_dir() {
+            return Some(current);
+        }
+        if !current.pop() {
+            // Reached root directory
+            return None;
+        }
+    }
+}
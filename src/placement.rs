@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::comments;
+
+/// Config directive for preamble rules, eg `preamble xml line ^<\?xml`
+pub const PREAMBLE_DIRECTIVE: &str = "preamble";
+
+/// Extension key for preamble rules that apply to every file
+const ANY_EXTENSION: &str = "*";
+
+/// A leading part of a file that must stay above the path header
+#[derive(Clone, Debug)]
+pub enum Preamble {
+    /// A single line matching the pattern, eg `<?xml version="1.0"?>`
+    Line(Regex),
+    /// A block opened by a line matching `start` and closed by the next line matching `end`,
+    /// eg a `---` front matter block
+    Block { start: Regex, end: Regex },
+}
+
+impl Preamble {
+    /// If this preamble starts at `lines[index]`, returns the index of the first line after it
+    fn skip(&self, lines: &[&str], index: usize) -> Option<usize> {
+        let line = lines.get(index)?;
+        match self {
+            Preamble::Line(re) => re.is_match(line).then_some(index + 1),
+            Preamble::Block { start, end } => {
+                if !start.is_match(line) {
+                    return None;
+                }
+                // An unterminated block isn't a preamble, just a line that looks like one
+                lines[index + 1..]
+                    .iter()
+                    .position(|l| end.is_match(l))
+                    .map(|offset| index + offset + 2)
+            }
+        }
+    }
+}
+
+/// Preamble rules keyed by lowercase extension (`*` applies to all files)
+pub type PreambleConfig = HashMap<String, Vec<Preamble>>;
+
+pub fn default_preambles() -> PreambleConfig {
+    parse_preambles(comments::DEFAULT_CONFIG, &mut Vec::new())
+}
+
+/// Parses the `preamble` directives from a config file, ignoring all other lines.
+///
+/// Format:
+///   preamble <extension> line <regex>
+///   preamble <extension> block <start regex> <end regex>
+pub fn parse_preambles(content: &str, warnings: &mut Vec<String>) -> PreambleConfig {
+    let mut preambles = PreambleConfig::new();
+    merge_preambles(&mut preambles, content, warnings);
+    preambles
+}
+
+/// Adds the `preamble` directives found in `content` to an existing set of rules, adding a
+/// warning to `warnings` for each rule it skips
+pub fn merge_preambles(preambles: &mut PreambleConfig, content: &str, warnings: &mut Vec<String>) {
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.first() != Some(&PREAMBLE_DIRECTIVE) {
+            continue;
+        }
+
+        let (extension, kind, patterns) = match parts.as_slice() {
+            [_, extension, kind, patterns @ ..] if !patterns.is_empty() => (
+                extension.trim_start_matches('.').to_lowercase(),
+                *kind,
+                patterns,
+            ),
+            _ => {
+                warnings.push(format!(
+                    "Incomplete preamble rule '{}' in config file, skipping",
+                    line.trim()
+                ));
+                continue;
+            }
+        };
+
+        let mut compile = |pattern: &str| match Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                warnings.push(format!(
+                    "Invalid preamble pattern '{pattern}' for extension '.{extension}': {e}, skipping"
+                ));
+                None
+            }
+        };
+
+        let preamble = match (kind, patterns) {
+            ("line", _) => compile(&patterns.join(" ")).map(Preamble::Line),
+            ("block", [start, end]) => compile(start)
+                .zip(compile(end))
+                .map(|(start, end)| Preamble::Block { start, end }),
+            _ => {
+                warnings.push(format!(
+                    "Unknown preamble rule '{}' in config file, skipping",
+                    line.trim()
+                ));
+                None
+            }
+        };
+
+        if let Some(preamble) = preamble {
+            preambles.entry(extension).or_default().push(preamble);
+        }
+    }
+}
+
+/// Returns the rules that apply to files with the given (lowercase) extension
+pub fn rules_for<'a>(preambles: &'a PreambleConfig, extension: Option<&str>) -> Vec<&'a Preamble> {
+    let any = preambles.get(ANY_EXTENSION).into_iter().flatten();
+    let specific = extension
+        .and_then(|ext| preambles.get(ext))
+        .into_iter()
+        .flatten();
+    any.chain(specific).collect()
+}
+
+/// Returns the index of the line the path header belongs on: right after every
+/// leading preamble (shebang, XML declaration, front matter, ...) in the file.
+pub fn header_insert_index(lines: &[&str], rules: &[&Preamble]) -> usize {
+    let mut index = 0;
+    'outer: loop {
+        for rule in rules {
+            if let Some(next) = rule.skip(lines, index) {
+                index = next;
+                continue 'outer;
+            }
+        }
+        return index;
+    }
+}
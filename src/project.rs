@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+
+use crate::{args::Args, find_git_root};
+
+/// Project config file, looked up from the first target up to the base directory.
+/// Nearer files override farther ones.
+pub const PROJECT_CONFIG_FILENAME: &str = ".path-comment.toml";
+
+/// The content of a single `.path-comment.toml`
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ProjectFile {
+    /// `git` (the repository root), `cwd`, or a directory relative to the config file
+    base: Option<String>,
+    /// Header template, eg `File: {path}`
+    header: Option<String>,
+    /// Comment styles by extension, merged over the defaults
+    styles: BTreeMap<String, String>,
+    /// Comment styles by exact file name, eg `Makefile` or `.envrc`
+    names: BTreeMap<String, String>,
+    /// Encodings by extension or glob
+    encodings: BTreeMap<String, String>,
+    /// Gitignore patterns, relative to the config file
+    ignore: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    extensions: Option<String>,
+    /// Config file for extensions and comment styles, relative to the config file
+    config: Option<String>,
+    threads: Option<usize>,
+    keep: Option<bool>,
+    clean: Option<bool>,
+    force: Option<bool>,
+    no_recursive: Option<bool>,
+    no_ignore_merge: Option<bool>,
+    preserve_mtime: Option<bool>,
+    sniff: Option<bool>,
+    /// Built from `ignore` once the file's directory is known
+    #[serde(skip)]
+    ignore_rules: Vec<Gitignore>,
+}
+
+impl ProjectFile {
+    /// Reads the config file at `path`, resolving its relative paths. Invalid files are
+    /// skipped with a warning added to `warnings`.
+    fn load(path: &Path, warnings: &mut Vec<String>) -> Option<Self> {
+        let content = fs::read_to_string(path)
+            .inspect_err(|e| warnings.push(format!("Could not read {}: {e}", path.display())))
+            .ok()?;
+        let mut file: Self = toml::from_str(&content)
+            .inspect_err(|e| warnings.push(format!("Invalid config in {}: {e}", path.display())))
+            .ok()?;
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let resolve = |value: &String| dir.join(value).to_string_lossy().into_owned();
+        file.base = file.base.map(|base| match base.as_str() {
+            "git" | "cwd" => base,
+            _ => resolve(&base),
+        });
+        file.config = file.config.as_ref().map(resolve);
+
+        if !file.ignore.is_empty() {
+            let mut builder = GitignoreBuilder::new(dir);
+            for pattern in &file.ignore {
+                if let Err(e) = builder.add_line(Some(path.to_path_buf()), pattern) {
+                    warnings.push(format!("Invalid ignore pattern in {}: {e}", path.display()));
+                }
+            }
+            match builder.build() {
+                Ok(rules) => file.ignore_rules.push(rules),
+                Err(e) => warnings.push(format!("Invalid ignore rules in {}: {e}", path.display())),
+            }
+        }
+        Some(file)
+    }
+
+    /// Merges a config file nearer to the targets into this one
+    fn merge(&mut self, nearer: Self) {
+        self.base = nearer.base.or(self.base.take());
+        self.header = nearer.header.or(self.header.take());
+        self.styles.extend(nearer.styles);
+        self.names.extend(nearer.names);
+        self.encodings.extend(nearer.encodings);
+        self.include.extend(nearer.include);
+        self.exclude.extend(nearer.exclude);
+        self.extensions = nearer.extensions.or(self.extensions.take());
+        self.config = nearer.config.or(self.config.take());
+        self.threads = nearer.threads.or(self.threads);
+        self.keep = nearer.keep.or(self.keep);
+        self.clean = nearer.clean.or(self.clean);
+        self.force = nearer.force.or(self.force);
+        self.no_recursive = nearer.no_recursive.or(self.no_recursive);
+        self.no_ignore_merge = nearer.no_ignore_merge.or(self.no_ignore_merge);
+        self.preserve_mtime = nearer.preserve_mtime.or(self.preserve_mtime);
+        self.sniff = nearer.sniff.or(self.sniff);
+        // Nearer rules are checked first
+        let farther = std::mem::replace(&mut self.ignore_rules, nearer.ignore_rules);
+        self.ignore_rules.extend(farther);
+    }
+}
+
+/// The settings from the `.path-comment.toml` files that don't map to a command line flag
+#[derive(Clone, Default, Debug)]
+pub struct ProjectConfig {
+    /// The config files that were loaded, farthest first
+    pub files: Vec<PathBuf>,
+    /// Header template, eg `File: {path}`
+    pub header: Option<String>,
+    /// Comment styles by extension, as delimiters or style names
+    pub styles: BTreeMap<String, String>,
+    /// Comment styles by exact file name, as delimiters or style names
+    pub names: BTreeMap<String, String>,
+    /// Encoding labels by extension or glob
+    pub encodings: BTreeMap<String, String>,
+    /// Ignore rules of each config file, nearest first
+    pub ignore_rules: Vec<Gitignore>,
+    /// Problems found while loading the config files, which were skipped in part or whole
+    pub warnings: Vec<String>,
+}
+
+/// Finds the `.path-comment.toml` files from `start_dir` up to `stop_dir` (or the filesystem
+/// root), farthest first
+pub fn discover(start_dir: &Path, stop_dir: Option<&Path>) -> Vec<PathBuf> {
+    let dirs: Vec<&Path> = match stop_dir {
+        Some(stop) if start_dir.starts_with(stop) => start_dir
+            .ancestors()
+            .take_while(|dir| dir.starts_with(stop))
+            .collect(),
+        Some(_) => vec![start_dir],
+        None => start_dir.ancestors().collect(),
+    };
+    let mut files: Vec<PathBuf> = dirs
+        .into_iter()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILENAME))
+        .filter(|file| file.is_file())
+        .collect();
+    files.reverse();
+    files
+}
+
+/// Loads the `.path-comment.toml` files applying to the targets in `args`, and fills in the
+/// options that weren't given on the command line. Flags set in a config file are turned off
+/// with their `--no-` counterpart (`--recursive` and `--ignore-merge` for the `no-` ones), and
+/// its lists are only used when the command line has none.
+pub fn apply(args: &mut Args) {
+    let first_target = args
+        .stdin_path
+        .as_ref()
+        .or(args.paths.first())
+        .map_or(".", String::as_str);
+    let Ok(target) = std::path::absolute(first_target) else {
+        return;
+    };
+    // The base directory is canonical, so the lookup starts from a canonical path too
+    let target = target.canonicalize().unwrap_or(target);
+    let start_dir = if target.is_dir() {
+        target.as_path()
+    } else {
+        target.parent().unwrap_or(&target)
+    };
+    let stop_dir = match &args.base {
+        Some(base) => Path::new(base).canonicalize().ok(),
+        None if !args.no_git => find_git_root(start_dir),
+        None => None,
+    };
+
+    let mut files = discover(start_dir, stop_dir.as_deref());
+    let mut merged = ProjectFile::default();
+    let mut warnings = Vec::new();
+    files.retain(|file| {
+        ProjectFile::load(file, &mut warnings)
+            .map(|project_file| merged.merge(project_file))
+            .is_some()
+    });
+
+    // An explicit --base or --no-git wins over the base policy
+    if args.base.is_none() && !args.no_git {
+        match merged.base.as_deref() {
+            None | Some("git") => {}
+            Some("cwd") => {
+                args.base = env::current_dir()
+                    .ok()
+                    .map(|dir| dir.to_string_lossy().into_owned());
+            }
+            Some(dir) => args.base = Some(dir.to_string()),
+        }
+    }
+
+    if args.include.is_empty() {
+        args.include = merged.include;
+    }
+    if args.exclude.is_empty() {
+        args.exclude = merged.exclude;
+    }
+    args.extensions = args.extensions.take().or(merged.extensions);
+    args.config_file = args.config_file.take().or(merged.config);
+    args.threads = args.threads.or(merged.threads);
+    merge_flag(&mut args.keep, args.no_keep, merged.keep);
+    merge_flag(&mut args.clean, args.no_clean, merged.clean);
+    merge_flag(&mut args.force, args.no_force, merged.force);
+    merge_flag(&mut args.no_recursive, args.recursive, merged.no_recursive);
+    merge_flag(
+        &mut args.no_ignore_merge,
+        args.ignore_merge,
+        merged.no_ignore_merge,
+    );
+    merge_flag(
+        &mut args.preserve_mtime,
+        args.no_preserve_mtime,
+        merged.preserve_mtime,
+    );
+    merge_flag(&mut args.sniff, args.no_sniff, merged.sniff);
+
+    args.project = ProjectConfig {
+        files,
+        header: merged.header,
+        styles: merged.styles,
+        names: merged.names,
+        encodings: merged.encodings,
+        ignore_rules: merged.ignore_rules,
+        warnings,
+    };
+}
+
+/// Sets `flag` from the config files, unless the command line turned it on or off (`negated`)
+fn merge_flag(flag: &mut bool, negated: bool, configured: Option<bool>) {
+    if !*flag && !negated {
+        *flag = configured.unwrap_or(false);
+    }
+}
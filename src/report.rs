@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+
+use crate::{comments, transform};
+
+/// Why a file was not given a header
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SkipReason {
+    /// No comment style is configured for the file
+    NoStyle,
+    /// The file isn't valid UTF-8 or its configured encoding (likely binary)
+    NonUtf8,
+    /// The report is for a directory that was skipped along with everything in it
+    IgnoredDir,
+    /// The new content couldn't be written to the file
+    WriteError,
+    /// The file already has a docstring, which a `docstring` style header would replace
+    Docstring,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::NoStyle => "no-style",
+            SkipReason::NonUtf8 => "non-utf8",
+            SkipReason::IgnoredDir => "ignored-dir",
+            SkipReason::WriteError => "write-error",
+            SkipReason::Docstring => "docstring",
+        }
+    }
+}
+
+/// What happened to a single file
+#[derive(Clone, Debug)]
+pub enum FileOutcome {
+    /// The header was applied; `update.changed` tells whether the file was (or would be) modified
+    Processed {
+        style: comments::Style,
+        update: transform::HeaderUpdate,
+        /// Unified diff of the change, when requested with `--diff` and the file changed
+        diff: Option<String>,
+    },
+    Skipped(SkipReason),
+}
+
+/// The result of processing a single file
+#[derive(Clone, Debug)]
+pub struct FileReport {
+    /// Relative to the base directory, like the header
+    pub path: PathBuf,
+    pub outcome: FileOutcome,
+    /// The path the file had before it was renamed, with `--git-renamed`
+    pub renamed_from: Option<PathBuf>,
+}
+
+impl FileReport {
+    /// The header update, if the file was processed
+    pub fn update(&self) -> Option<&transform::HeaderUpdate> {
+        match &self.outcome {
+            FileOutcome::Processed { update, .. } => Some(update),
+            FileOutcome::Skipped(_) => None,
+        }
+    }
+
+    /// The machine-readable record for `--format json`/`ndjson`
+    pub fn to_json(&self) -> Value {
+        let (style, action, reason, update, diff) = match &self.outcome {
+            FileOutcome::Processed {
+                style,
+                update,
+                diff,
+            } => (
+                Some(style.name()),
+                update.action.as_str(),
+                None,
+                Some(update),
+                diff.as_deref(),
+            ),
+            FileOutcome::Skipped(reason) => (None, "skipped", Some(reason.as_str()), None, None),
+        };
+        // The header the file had before: the correct one, or the first stale path comment
+        let old_header = update.and_then(|update| {
+            if update.had_header {
+                Some(update.header.as_str())
+            } else {
+                update.stripped.first().map(|line| line.trim())
+            }
+        });
+        let new_header = update
+            .filter(|update| update.action != transform::HeaderAction::Removed)
+            .map(|update| update.header.as_str());
+
+        json!({
+            "type": "file",
+            "path": display_path(&self.path),
+            "renamed_from": self.renamed_from.as_deref().map(display_path),
+            "style": style,
+            "action": action,
+            "reason": reason,
+            "old_header": old_header,
+            "new_header": new_header,
+            "diff": diff,
+        })
+    }
+}
+
+/// A path with forward slashes, for the JSON records
+fn display_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
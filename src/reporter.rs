@@ -0,0 +1,316 @@
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    args::{Args, ColorChoice, OutputFormat, SortChoice},
+    report::{FileOutcome, FileReport, SkipReason},
+};
+
+/// Totals printed once every file is done
+#[derive(Copy, Clone, Debug)]
+pub struct Summary {
+    pub processed: usize,
+    pub skipped: usize,
+    pub dry_run: bool,
+}
+
+impl Summary {
+    /// The summary record for `--format json`/`ndjson`
+    pub fn to_json(self) -> Value {
+        json!({
+            "type": "summary",
+            "processed": self.processed,
+            "skipped": self.skipped,
+            "dry_run": self.dry_run,
+        })
+    }
+}
+
+/// Prints the per-file output of a run. Errors writing stdout are returned, so that a closed
+/// pipe (`path-comment --format ndjson . | head -1`) stops the run instead of panicking.
+pub trait Reporter: Sync {
+    /// Called for every file as soon as it's done, possibly from several walker threads
+    fn report(&self, report: &FileReport) -> io::Result<()>;
+
+    /// Called once after every file has been reported
+    fn finish(&self, summary: &Summary) -> io::Result<()>;
+}
+
+/// The reporter for the output options in `args`
+pub fn for_args(args: &Args) -> Box<dyn Reporter> {
+    let reporter: Box<dyn Reporter> = match args.format {
+        OutputFormat::Json => Box::new(JsonReporter::default()),
+        OutputFormat::Ndjson => Box::new(NdjsonReporter),
+        OutputFormat::Text if args.diff => Box::new(DiffReporter),
+        OutputFormat::Text if args.hook => Box::new(HookReporter),
+        OutputFormat::Text => Box::new(TextReporter {
+            palette: Palette {
+                color: use_color(args.color),
+            },
+            clean: args.clean,
+        }),
+    };
+
+    if use_sorting(args.sort, args.format) {
+        Box::new(SortedReporter::new(reporter))
+    } else {
+        reporter
+    }
+}
+
+/// Whether to sort the output by path: `auto` sorts it when stdout isn't a terminal (logs,
+/// snapshots, ...), except for `ndjson` which is meant to be streamed
+pub fn use_sorting(choice: SortChoice, format: OutputFormat) -> bool {
+    match choice {
+        SortChoice::Always => true,
+        SortChoice::Never => false,
+        SortChoice::Auto => format != OutputFormat::Ndjson && !io::stdout().is_terminal(),
+    }
+}
+
+/// Whether to color the output: `auto` colors only when stdout is a terminal and `NO_COLOR`
+/// isn't set, see <https://no-color.org>
+pub fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && io::stdout().is_terminal()
+        }
+    }
+}
+
+/// Holds the reports back until the end, then passes them on to `inner` sorted by path,
+/// so the output doesn't depend on the order the walker threads finish in
+pub struct SortedReporter {
+    inner: Box<dyn Reporter>,
+    reports: Mutex<Vec<FileReport>>,
+}
+
+impl SortedReporter {
+    pub fn new(inner: Box<dyn Reporter>) -> Self {
+        Self {
+            inner,
+            reports: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Reporter for SortedReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        self.reports.lock().unwrap().push(report.clone());
+        Ok(())
+    }
+
+    fn finish(&self, summary: &Summary) -> io::Result<()> {
+        let mut reports = std::mem::take(&mut *self.reports.lock().unwrap());
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        for report in &reports {
+            self.inner.report(report)?;
+        }
+        self.inner.finish(summary)
+    }
+}
+
+/// Formats the `+`/`-` lines, with or without ANSI colors
+#[derive(Copy, Clone, Debug)]
+pub struct Palette {
+    pub color: bool,
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+
+impl Palette {
+    pub fn added(&self, s: &str) -> String {
+        const ANSI_GREEN: &str = "\x1b[32m";
+        self.paint(ANSI_GREEN, format!("+ {s}"))
+    }
+
+    pub fn removed(&self, s: &str) -> String {
+        const ANSI_RED: &str = "\x1b[31m";
+        self.paint(ANSI_RED, format!("- {s}"))
+    }
+
+    pub fn no_change(&self, s: &str) -> String {
+        const ANSI_YELLOW: &str = "\x1b[33m";
+        self.paint(ANSI_YELLOW, format!(" {s}"))
+    }
+
+    fn paint(&self, ansi: &str, s: String) -> String {
+        if self.color {
+            format!("{ansi}{s}{ANSI_RESET}")
+        } else {
+            s
+        }
+    }
+}
+
+/// The human readable `+`/`-` visualization of each file, followed by the summary
+pub struct TextReporter {
+    pub palette: Palette,
+    /// Whether the run removes headers, which changes how a correct header is shown
+    pub clean: bool,
+}
+
+impl Reporter for TextReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        let processed = match &report.renamed_from {
+            Some(old) => format!("{} → {}", old.display(), report.path.display()),
+            None => format!("{}", report.path.display()),
+        };
+
+        let update = match &report.outcome {
+            FileOutcome::Processed { update, .. } => update,
+            FileOutcome::Skipped(reason @ (SkipReason::NonUtf8 | SkipReason::Docstring)) => {
+                let message = if *reason == SkipReason::NonUtf8 {
+                    "Skipped non-UTF8 file"
+                } else {
+                    "Skipped file with a docstring"
+                };
+                // Use no_change style for visual consistency
+                return writeln!(
+                    io::stdout().lock(),
+                    "{} {}",
+                    processed,
+                    self.palette.no_change(message)
+                );
+            }
+            FileOutcome::Skipped(
+                SkipReason::NoStyle | SkipReason::IgnoredDir | SkipReason::WriteError,
+            ) => return Ok(()),
+        };
+        let first_line = &update.header;
+        let palette = &self.palette;
+        // A file's lines stay together even when several walker threads report at once
+        let mut out = io::stdout().lock();
+
+        if !update.changed {
+            if update.had_header {
+                writeln!(out, "{processed} {}", palette.no_change(first_line))?;
+            } else {
+                writeln!(out, "{processed} {}", palette.no_change("(no change)"))?;
+            }
+        } else if update.stripped.is_empty() {
+            if update.had_header {
+                // Only reachable with --clean, the correct header is being removed
+                writeln!(out, "{processed} {}", palette.removed(first_line))?;
+            } else {
+                writeln!(out, "{processed} {}", palette.added(first_line))?;
+            }
+        } else {
+            writeln!(out, "{processed} ")?;
+
+            if update.had_header {
+                if self.clean {
+                    writeln!(out, "{}", palette.removed(first_line))?;
+                } else {
+                    writeln!(out, "{}", palette.no_change(first_line))?;
+                }
+            }
+            // Show other path comments being removed
+            for line in &update.stripped {
+                writeln!(out, "{}", palette.removed(line))?;
+            }
+
+            if !update.had_header && !self.clean {
+                writeln!(out, "{}", palette.added(first_line))?;
+            }
+
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self, summary: &Summary) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        writeln!(out, "\nSummary:")?;
+        writeln!(out, "  Files processed: {}", summary.processed)?;
+        writeln!(out, "  Files skipped: {}", summary.skipped)?;
+
+        if summary.dry_run {
+            writeln!(out, "\nThis was a dry run. No files were modified.")?;
+        }
+        Ok(())
+    }
+}
+
+/// A line per changed file and nothing else, for `--hook`
+pub struct HookReporter;
+
+impl Reporter for HookReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        match report.update().filter(|update| update.changed) {
+            Some(update) => writeln!(
+                io::stdout().lock(),
+                "{}: {} `{}`",
+                report.path.display(),
+                update.action.as_str(),
+                update.header
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&self, _summary: &Summary) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Unified diffs of the changed files only, for `--diff`
+pub struct DiffReporter;
+
+impl Reporter for DiffReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        match &report.outcome {
+            FileOutcome::Processed {
+                diff: Some(diff), ..
+            } => write!(io::stdout().lock(), "{diff}"),
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(&self, _summary: &Summary) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A single JSON object with every file record and the summary, printed once done
+#[derive(Default)]
+pub struct JsonReporter {
+    files: Mutex<Vec<Value>>,
+}
+
+impl Reporter for JsonReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        self.files.lock().unwrap().push(report.to_json());
+        Ok(())
+    }
+
+    fn finish(&self, summary: &Summary) -> io::Result<()> {
+        let files = std::mem::take(&mut *self.files.lock().unwrap());
+        let output = json!({
+            "files": files,
+            "summary": summary.to_json(),
+        });
+        writeln!(io::stdout().lock(), "{output:#}")
+    }
+}
+
+/// A JSON record per line, printed as soon as each file is done
+pub struct NdjsonReporter;
+
+impl Reporter for NdjsonReporter {
+    fn report(&self, report: &FileReport) -> io::Result<()> {
+        writeln!(io::stdout().lock(), "{}", report.to_json())
+    }
+
+    fn finish(&self, summary: &Summary) -> io::Result<()> {
+        writeln!(io::stdout().lock(), "{}", summary.to_json())
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::{comments, transform::HeaderOptions};
+
+/// Config directive for multi-language files, eg `resolve vue after slash ^<script\b`
+pub const RESOLVE_DIRECTIVE: &str = "resolve";
+
+/// Where the header goes relative to the opening line a rule matched
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Position {
+    /// Above it, after the preambles like any other header, eg `<!-- -->` above `<template>`
+    Before,
+    /// Right after it, eg `//` inside a `<script>` block
+    After,
+}
+
+/// Picks the style of a multi-language file when its opening line matches `opening`
+#[derive(Clone, Debug)]
+pub struct ResolveRule {
+    pub opening: Regex,
+    pub style: comments::Style,
+    pub position: Position,
+}
+
+/// Resolve rules keyed by lowercase extension, checked in order
+pub type ResolveConfig = HashMap<String, Vec<ResolveRule>>;
+
+pub fn default_rules() -> ResolveConfig {
+    let mut rules = ResolveConfig::new();
+    let styles = comments::CommentConfig::default();
+    merge_rules(
+        &mut rules,
+        comments::DEFAULT_CONFIG,
+        &styles,
+        &mut Vec::new(),
+    );
+    rules
+}
+
+/// Adds the `resolve` directives found in `content` to an existing set of rules, ignoring all
+/// other lines. Custom styles are looked up in `styles`, and skipped rules are reported in
+/// `warnings`.
+///
+/// Format:
+///   resolve <extension> <before|after> <style> <opening line regex>
+///
+/// The style is a single word: a style name, or delimiters without spaces like `//`.
+pub fn merge_rules(
+    rules: &mut ResolveConfig,
+    content: &str,
+    styles: &comments::CommentConfig,
+    warnings: &mut Vec<String>,
+) {
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.first() != Some(&RESOLVE_DIRECTIVE) {
+            continue;
+        }
+
+        let [_, extension, position, style, pattern @ ..] = parts.as_slice() else {
+            warnings.push(format!(
+                "Incomplete resolve rule '{}' in config file, skipping",
+                line.trim()
+            ));
+            continue;
+        };
+        if pattern.is_empty() {
+            warnings.push(format!(
+                "Incomplete resolve rule '{}' in config file, skipping",
+                line.trim()
+            ));
+            continue;
+        }
+        let position = match *position {
+            "before" => Position::Before,
+            "after" => Position::After,
+            _ => {
+                warnings.push(format!(
+                    "Unknown position '{position}' in resolve rule '{}', use `before` or `after`, skipping",
+                    line.trim()
+                ));
+                continue;
+            }
+        };
+        let Some(style) = styles.parse_style(style) else {
+            warnings.push(format!(
+                "Unknown comment style '{style}' in resolve rule, skipping"
+            ));
+            continue;
+        };
+        let pattern = pattern.join(" ");
+        let opening = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(e) => {
+                warnings.push(format!(
+                    "Invalid resolve pattern '{pattern}' for extension '.{extension}': {e}, skipping"
+                ));
+                continue;
+            }
+        };
+
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        rules.entry(extension).or_default().push(ResolveRule {
+            opening,
+            style,
+            position,
+        });
+    }
+}
+
+/// The style and header position picked for a file
+#[derive(Clone, Debug)]
+pub struct Resolution<'a> {
+    pub style: comments::Style,
+    /// Put the header right after the first line matching this, instead of after the preambles
+    pub after: Option<&'a Regex>,
+    /// Other styles the file may use, whose path comments are stripped too
+    pub other_styles: Vec<comments::Style>,
+}
+
+impl<'a> Resolution<'a> {
+    /// Just `style`, at the usual position
+    pub fn fixed(style: comments::Style) -> Self {
+        Self {
+            style,
+            after: None,
+            other_styles: Vec::new(),
+        }
+    }
+
+    /// `options` with the header position and styles to strip of this resolution
+    pub fn options(&'a self, options: HeaderOptions<'a>) -> HeaderOptions<'a> {
+        HeaderOptions {
+            after: self.after,
+            other_styles: &self.other_styles,
+            ..options
+        }
+    }
+}
+
+/// Picks the style of `content` from the first of `rules` matching its opening line, the first
+/// line that isn't blank or a path comment. Falls back to `default` when none does.
+pub fn resolve<'a>(
+    rules: &[&'a ResolveRule],
+    content: &str,
+    default: comments::Style,
+) -> Resolution<'a> {
+    if rules.is_empty() {
+        return Resolution::fixed(default);
+    }
+
+    let mut candidates = vec![default];
+    for rule in rules {
+        if !candidates.contains(&rule.style) {
+            candidates.push(rule.style);
+        }
+    }
+
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let opening = content.lines().map(str::trim).find(|line| {
+        !line.is_empty() && !candidates.iter().any(|style| style.regex().is_match(line))
+    });
+    let rule = opening.and_then(|opening| {
+        rules
+            .iter()
+            .copied()
+            .find(|rule| rule.opening.is_match(opening))
+    });
+
+    let (style, after) = match rule {
+        Some(rule) => (
+            rule.style,
+            (rule.position == Position::After).then_some(&rule.opening),
+        ),
+        None => (default, None),
+    };
+    candidates.retain(|&candidate| candidate != style);
+    Resolution {
+        style,
+        after,
+        other_styles: candidates,
+    }
+}
+
+/// Returns the rules that apply to files with the given (lowercase) extension
+pub fn rules_for<'a>(rules: &'a ResolveConfig, extension: Option<&str>) -> Vec<&'a ResolveRule> {
+    extension
+        .and_then(|ext| rules.get(ext))
+        .into_iter()
+        .flatten()
+        .collect()
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::comments;
+
+/// Config directive for the interpreter table used by `--sniff`, eg `interpreter python3 #`
+pub const INTERPRETER_DIRECTIVE: &str = "interpreter";
+
+/// Number of lines at the start and end of a file searched for a vim modeline, like vim's
+/// default `modelines` option
+const MODELINE_LINES: usize = 5;
+
+/// Comment styles keyed by lowercase interpreter or modeline file type name
+pub type InterpreterConfig = HashMap<String, comments::Style>;
+
+pub fn default_interpreters() -> InterpreterConfig {
+    let mut interpreters = InterpreterConfig::new();
+    let styles = comments::CommentConfig::default();
+    merge_interpreters(
+        &mut interpreters,
+        comments::DEFAULT_CONFIG,
+        &styles,
+        &mut Vec::new(),
+    );
+    interpreters
+}
+
+/// Adds the `interpreter` directives found in `content` to an existing table, ignoring all
+/// other lines. Custom styles are looked up in `styles`, and skipped rules are reported in
+/// `warnings`.
+///
+/// Format:
+///   interpreter <name> <comment style>
+pub fn merge_interpreters(
+    interpreters: &mut InterpreterConfig,
+    content: &str,
+    styles: &comments::CommentConfig,
+    warnings: &mut Vec<String>,
+) {
+    for line in content.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.first() != Some(&INTERPRETER_DIRECTIVE) {
+            continue;
+        }
+
+        let [_, name, style @ ..] = parts.as_slice() else {
+            warnings.push(format!(
+                "Incomplete interpreter rule '{}' in config file, skipping",
+                line.trim()
+            ));
+            continue;
+        };
+        match styles.parse_style(&style.join(" ")) {
+            Some(style) => {
+                interpreters.insert(name.to_lowercase(), style);
+            }
+            None => warnings.push(format!(
+                "Unknown comment style in interpreter rule '{}' in config file, skipping",
+                line.trim()
+            )),
+        }
+    }
+}
+
+/// Detects the comment style of `content` from its shebang, or else from a vim or emacs
+/// modeline. Returns `None` if neither names a known interpreter or file type.
+pub fn detect(content: &str, interpreters: &InterpreterConfig) -> Option<comments::Style> {
+    let lookup = |name: &str| {
+        let name = name.to_lowercase();
+        // `python3.12` falls back to `python3`, then to `python`
+        let unversioned = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        let major = name
+            .split_once('.')
+            .map_or(name.as_str(), |(major, _)| major);
+        [name.as_str(), major, unversioned]
+            .into_iter()
+            .find_map(|name| interpreters.get(name).copied())
+    };
+
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let lines: Vec<&str> = content.lines().collect();
+
+    if let Some(style) = lines
+        .first()
+        .and_then(|line| shebang_interpreter(line))
+        .and_then(lookup)
+    {
+        return Some(style);
+    }
+    modeline_file_type(&lines).and_then(lookup)
+}
+
+/// The interpreter named by a shebang line, eg `python3` for `#!/usr/bin/env -S python3 -u`
+pub fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?;
+    let program = program.rsplit('/').next().unwrap_or(program);
+    if program != "env" {
+        return Some(program);
+    }
+    // Skip the options and variable assignments given to env
+    words.find(|word| !word.starts_with('-') && !word.contains('='))
+}
+
+static VIM_MODELINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|\s)(?:vi|vim|ex)(?:[<=>]?\d+)?:.*?[\s:](?:ft|filetype|syn|syntax)=([\w+.-]+)")
+        .expect("valid vim modeline regex")
+});
+
+static EMACS_MODELINE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"-\*-(.*?)-\*-").expect("valid emacs modeline regex"));
+
+/// The file type named by a vim modeline in the first or last lines, or by an emacs one in the
+/// first two lines, eg `python` for `# vim: set ft=python:` or `# -*- mode: python -*-`
+pub fn modeline_file_type<'a>(lines: &[&'a str]) -> Option<&'a str> {
+    let head = &lines[..lines.len().min(MODELINE_LINES)];
+    let tail = &lines[lines.len().saturating_sub(MODELINE_LINES)..];
+    let vim = head
+        .iter()
+        .chain(tail)
+        .find_map(|line| VIM_MODELINE.captures(line))
+        .and_then(|captures| captures.get(1));
+    if let Some(file_type) = vim {
+        return Some(file_type.as_str());
+    }
+
+    // Emacs only looks at the first line, or the second one after a shebang
+    let emacs = lines
+        .iter()
+        .take(2)
+        .find_map(|line| EMACS_MODELINE.captures(line))?
+        .get(1)?
+        .as_str();
+    if !emacs.contains(':') {
+        // `-*- python -*-` is a short form for `-*- mode: python -*-`
+        return Some(emacs.trim()).filter(|mode| !mode.is_empty());
+    }
+    emacs.split(';').find_map(|setting| {
+        let (key, value) = setting.split_once(':')?;
+        (key.trim().eq_ignore_ascii_case("mode")).then(|| value.trim())
+    })
+}
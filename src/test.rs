@@ -1,3 +1,4 @@
+use clap::Parser;
 use std::{
     fs::{self, File, create_dir_all},
     io::Write,
@@ -6,9 +7,13 @@ use std::{
 use tempfile::TempDir;
 
 use crate::{
-    args::Args,
+    args::{Args, ColorChoice, OutputFormat, SortChoice},
     cli::{self},
     comments::Style,
+    config::Config,
+    project,
+    report::{FileOutcome, SkipReason},
+    reporter, transform,
 };
 
 struct TestArgsBuilder {
@@ -23,19 +28,43 @@ impl TestArgsBuilder {
         let path = temp_dir.path().to_path_buf();
         Self {
             args: Args {
-                dir: path.to_string_lossy().to_string(), // Default dir to temp dir
+                paths: vec![path.to_string_lossy().to_string()], // Default dir to temp dir
                 base: None,
                 no_git: false, // Default to allowing git search
                 extensions: None,
                 config_file: None,
                 no_recursive: false,
+                recursive: false,
                 dry_run: false,
+                check: false,
+                stdin_path: None,
                 comment_style: None,
                 force: false,
+                no_force: false,
                 keep: false,
+                no_keep: false,
                 print_extensions: false,
                 no_ignore_merge: false, // Default to allowing merge
+                ignore_merge: false,
                 clean: false,
+                no_clean: false,
+                include: Vec::new(),
+                exclude: Vec::new(),
+                preserve_mtime: false,
+                no_preserve_mtime: false,
+                diff: false,
+                format: OutputFormat::Text,
+                color: ColorChoice::Never,
+                sort: SortChoice::Never,
+                threads: None,
+                git_staged: false,
+                git_changed: None,
+                git_tracked: false,
+                git_renamed: None,
+                hook: false,
+                sniff: false,
+                no_sniff: false,
+                project: Default::default(),
             },
             temp_dir_path: path,
         }
@@ -43,11 +72,21 @@ impl TestArgsBuilder {
 
     // Helper to set the processing dir relative to temp_dir
     fn dir(mut self, relative_dir: &str) -> Self {
-        self.args.dir = self
-            .temp_dir_path
-            .join(relative_dir)
-            .to_string_lossy()
-            .to_string();
+        self.args.paths = vec![
+            self.temp_dir_path
+                .join(relative_dir)
+                .to_string_lossy()
+                .to_string(),
+        ];
+        self
+    }
+
+    // Helper to set several processing paths relative to temp_dir
+    fn paths(mut self, relative_paths: &[&str]) -> Self {
+        self.args.paths = relative_paths
+            .iter()
+            .map(|p| self.temp_dir_path.join(p).to_string_lossy().to_string())
+            .collect();
         self
     }
 
@@ -72,8 +111,6 @@ impl TestArgsBuilder {
         self
     }
 
-    // TODO: Test this!
-    #[allow(unused)]
     fn config_file(mut self, config_file: &str) -> Self {
         // Assume config file is relative to temp dir root for tests
         self.args.config_file = Some(
@@ -92,13 +129,73 @@ impl TestArgsBuilder {
         self
     }
 
+    fn include(mut self, glob: &str) -> Self {
+        self.args.include.push(glob.to_string());
+        self
+    }
+
+    fn exclude(mut self, glob: &str) -> Self {
+        self.args.exclude.push(glob.to_string());
+        self
+    }
+
     fn dry_run(mut self, dry_run: bool) -> Self {
         self.args.dry_run = dry_run;
         self
     }
 
+    fn preserve_mtime(mut self, preserve_mtime: bool) -> Self {
+        self.args.preserve_mtime = preserve_mtime;
+        self
+    }
+
+    fn threads(mut self, threads: usize) -> Self {
+        self.args.threads = Some(threads);
+        self
+    }
+
+    fn git_staged(mut self, git_staged: bool) -> Self {
+        self.args.git_staged = git_staged;
+        self
+    }
+
+    fn git_changed(mut self, rev: &str) -> Self {
+        self.args.git_changed = Some(rev.to_string());
+        self
+    }
+
+    fn git_tracked(mut self, git_tracked: bool) -> Self {
+        self.args.git_tracked = git_tracked;
+        self
+    }
+
+    fn git_renamed(mut self, range: Option<&str>) -> Self {
+        self.args.git_renamed = Some(range.map(str::to_string));
+        self
+    }
+
+    fn hook(mut self, hook: bool) -> Self {
+        self.args.hook = hook;
+        self
+    }
+
+    fn diff(mut self, diff: bool) -> Self {
+        self.args.diff = diff;
+        self
+    }
+
+    fn check(mut self, check: bool) -> Self {
+        self.args.check = check;
+        self
+    }
+
     fn comment_style(mut self, style: Style) -> Self {
-        self.args.comment_style = Some(style);
+        self.args.comment_style = Some(style.name().to_string());
+        self
+    }
+
+    fn sniff(mut self, sniff: bool) -> Self {
+        self.args.sniff = sniff;
         self
     }
 
@@ -133,9 +230,9 @@ fn create_test_file(base_dir: &Path, relative_name: &str, content: &str) -> Path
 // Simulates the logic in main.rs
 fn determine_test_paths(args: &Args, temp_root: &Path) -> (PathBuf, Option<PathBuf>) {
     let mut git_base_used = false;
-    let start_dir = Path::new(&args.dir)
+    let start_dir = Path::new(&args.paths[0])
         .canonicalize()
-        .unwrap_or_else(|_| panic!("Test dir {} not found", args.dir));
+        .unwrap_or_else(|_| panic!("Test dir {} not found", args.paths[0]));
 
     let base_dir = match args.base {
         Some(ref base) => temp_root
@@ -418,13 +515,7 @@ fn test_gitignore_merge() {
     create_dir_all(git_root.join(".git")).unwrap(); // Needs .git to trigger merge logic
 
     // Create a .gitignore file
-    let gitignore_content = r#"
- # Comment line
- build/
- /dist
- *.log
- vendor # Simple name
-     "#;
+    let gitignore_content = "# Comment line\nout/\n/generated\n*.log\nvendor # not a comment\n";
     create_test_file(&git_root, ".gitignore", gitignore_content);
 
     // Process starting inside the repo
@@ -440,12 +531,17 @@ fn test_gitignore_merge() {
 
     // Check defaults are still there
     assert!(processor.ignored_dirs().contains("node_modules"));
-    // Check simple merges from .gitignore
-    assert!(processor.ignored_dirs().contains("build")); // Trailing / removed
-    assert!(processor.ignored_dirs().contains("dist")); // Leading / removed (simplistic parsing)
-    assert!(processor.ignored_dirs().contains("vendor"));
-    // Check complex pattern was NOT added by simple parsing
-    assert!(!processor.ignored_dirs().contains("*.log"));
+    // Unanchored directory patterns match at any depth
+    assert!(processor.should_skip_directory(&git_root.join("out")));
+    assert!(processor.should_skip_directory(&git_root.join("sub/out")));
+    // Anchored patterns only match relative to the .gitignore
+    assert!(processor.should_skip_directory(&git_root.join("generated")));
+    assert!(!processor.should_skip_directory(&git_root.join("sub/generated")));
+    // Globs match files too
+    assert!(processor.is_gitignored(&git_root.join("debug.log"), false));
+    assert!(processor.is_gitignored(&git_root.join("sub/trace.log"), false));
+    // A `#` after a pattern is part of the pattern, not a comment
+    assert!(!processor.should_skip_directory(&git_root.join("vendor")));
 }
 
 #[test]
@@ -455,6 +551,7 @@ fn test_gitignore_merge_disabled() {
     create_dir_all(&git_root).unwrap();
     create_dir_all(git_root.join(".git")).unwrap();
     create_test_file(&git_root, ".gitignore", "build/\nvendor\n");
+    let vendor_file = create_test_file(&git_root, "vendor/lib.js", "lib();\n");
 
     // Disable merging
     let (args, _temp_path) = TestArgsBuilder::new(&temp_dir)
@@ -466,13 +563,55 @@ fn test_gitignore_merge_disabled() {
     assert_eq!(base_dir, git_root.canonicalize().unwrap());
     assert!(gitignore_path.is_none()); // Ensure gitignore path is None due to flag
 
-    let processor = cli::Cli::new(args, base_dir, gitignore_path); // Pass None for gitignore
+    let processor = cli::Cli::new_arc(args, base_dir, gitignore_path); // Pass None for gitignore
 
     // Check defaults are there
     assert!(processor.ignored_dirs().contains("node_modules"));
-    assert!(processor.ignored_dirs().contains("build")); // Default ignore should still be present
-    // Check ignores from .gitignore were NOT merged
-    assert!(!processor.ignored_dirs().contains("vendor")); // THIS is the correct check
+    assert!(processor.should_skip_directory(&git_root.join("build"))); // Default ignore should still be present
+    // Check ignores from .gitignore were NOT applied, by us or by the walker
+    assert!(!processor.should_skip_directory(&git_root.join("vendor")));
+    processor.run();
+    assert_eq!(
+        "// vendor/lib.js\nlib();\n",
+        fs::read_to_string(&vendor_file).unwrap()
+    );
+}
+
+#[test]
+fn test_gitignore_nested_and_negated() {
+    let temp_dir = TempDir::new().unwrap();
+    let git_root = temp_dir.path().join("my_repo");
+    create_dir_all(git_root.join(".git")).unwrap();
+    create_test_file(
+        &git_root,
+        ".gitignore",
+        "*.generated.ts\n!keep.generated.ts\n",
+    );
+    // Anchored pattern in a sub-project only applies inside that sub-project
+    create_test_file(&git_root, "sub/.gitignore", "/out\n");
+
+    let sub_out = create_test_file(&git_root, "sub/out/a.js", "a();\n");
+    let root_out = create_test_file(&git_root, "out/b.js", "b();\n");
+    let generated = create_test_file(&git_root, "src/api.generated.ts", "api();\n");
+    let kept = create_test_file(&git_root, "src/keep.generated.ts", "keep();\n");
+
+    let (args, _temp_path) = TestArgsBuilder::new(&temp_dir)
+        .dir(git_root.to_str().unwrap())
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, temp_dir.path());
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!("a();\n", fs::read_to_string(&sub_out).unwrap());
+    assert_eq!(
+        "// out/b.js\nb();\n",
+        fs::read_to_string(&root_out).unwrap()
+    );
+    assert_eq!("api();\n", fs::read_to_string(&generated).unwrap());
+    assert_eq!(
+        "// src/keep.generated.ts\nkeep();\n",
+        fs::read_to_string(&kept).unwrap()
+    );
 }
 
 #[test]
@@ -516,7 +655,7 @@ fn test_directory_skipping_gitignore_merged() {
     let (base_dir, gitignore_path) = determine_test_paths(&args, temp_dir.path());
     let processor = cli::Cli::new(args.clone(), base_dir.clone(), gitignore_path.clone());
 
-    assert!(processor.ignored_dirs().contains("vendor")); // Check merge happened
+    assert!(!processor.ignored_dirs().contains("vendor")); // Not a plain name anymore
     assert!(processor.should_skip_directory(&vendor_dir));
     assert!(processor.should_skip_directory(&test_file));
 
@@ -550,6 +689,208 @@ fn test_directory_skipping_force() {
     assert_eq!(skipped, 0);
 }
 
+#[test]
+fn test_pathcommentignore() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        ".pathcommentignore",
+        "tests/fixtures/\n*.gen.js\n",
+    );
+    // Nested files apply to their own directory
+    create_test_file(temp_dir.path(), "lib/.pathcommentignore", "/vendored.js\n");
+
+    let fixture = create_test_file(temp_dir.path(), "tests/fixtures/case.js", "case();\n");
+    let test = create_test_file(temp_dir.path(), "tests/unit.js", "unit();\n");
+    let generated = create_test_file(temp_dir.path(), "src/api.gen.js", "api();\n");
+    let vendored = create_test_file(temp_dir.path(), "lib/vendored.js", "v();\n");
+    let not_vendored = create_test_file(temp_dir.path(), "vendored.js", "v();\n");
+
+    // Works without any git repository
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).no_git(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!("case();\n", fs::read_to_string(&fixture).unwrap());
+    assert_eq!(
+        "// tests/unit.js\nunit();\n",
+        fs::read_to_string(&test).unwrap()
+    );
+    assert_eq!("api();\n", fs::read_to_string(&generated).unwrap());
+    assert_eq!("v();\n", fs::read_to_string(&vendored).unwrap());
+    assert_eq!(
+        "// vendored.js\nv();\n",
+        fs::read_to_string(&not_vendored).unwrap()
+    );
+}
+
+#[test]
+fn test_pathcommentignore_explicit_files_and_defaults() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        ".pathcommentignore",
+        "gen/\n!node_modules/\n",
+    );
+    let generated = create_test_file(temp_dir.path(), "gen/api.js", "api();\n");
+    let package = create_test_file(temp_dir.path(), "node_modules/pkg/index.js", "pkg();\n");
+
+    // Explicitly listed files are still subject to .pathcommentignore
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["gen/api.js", "node_modules"])
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert!(cli_arc.should_skip_file(&generated));
+    // A negated pattern re-includes a directory skipped by the built-in defaults
+    assert!(!cli_arc.should_skip_directory(&temp_dir.path().join("node_modules")));
+    cli_arc.run();
+
+    assert_eq!("api();\n", fs::read_to_string(&generated).unwrap());
+    assert_eq!(
+        "// node_modules/pkg/index.js\npkg();\n",
+        fs::read_to_string(&package).unwrap()
+    );
+}
+
+#[test]
+fn test_include_exclude_globs() {
+    let temp_dir = TempDir::new().unwrap();
+    let core = create_test_file(temp_dir.path(), "crates/core/src/lib.rs", "fn core() {}\n");
+    let generated = create_test_file(
+        temp_dir.path(),
+        "crates/core/src/generated/api.rs",
+        "fn api() {}\n",
+    );
+    let fixture = create_test_file(
+        temp_dir.path(),
+        "crates/core/src/fixtures.rs",
+        "fn f() {}\n",
+    );
+    let outside = create_test_file(temp_dir.path(), "tools/build.rs", "fn build() {}\n");
+    // `*` doesn't cross directories
+    let nested = create_test_file(temp_dir.path(), "crates/a/b/src/lib.rs", "fn b() {}\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .include("crates/*/src/**")
+        .exclude("src/**/generated/**")
+        .exclude("**/generated")
+        .exclude("crates/core/src/fixtures.rs")
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!(
+        "// crates/core/src/lib.rs\nfn core() {}\n",
+        fs::read_to_string(&core).unwrap()
+    );
+    assert_eq!("fn api() {}\n", fs::read_to_string(&generated).unwrap());
+    assert_eq!("fn f() {}\n", fs::read_to_string(&fixture).unwrap());
+    assert_eq!("fn build() {}\n", fs::read_to_string(&outside).unwrap());
+    assert_eq!("fn b() {}\n", fs::read_to_string(&nested).unwrap());
+}
+
+#[test]
+fn test_exclude_glob_explicit_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let fixture = create_test_file(temp_dir.path(), "tests/fixtures/input.js", "input();\n");
+    let test = create_test_file(temp_dir.path(), "tests/main.js", "main();\n");
+
+    // Excluding a directory also excludes explicitly listed files inside it
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["tests/fixtures/input.js", "tests/main.js"])
+        .exclude("tests/fixtures")
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!("input();\n", fs::read_to_string(&fixture).unwrap());
+    assert_eq!(
+        "// tests/main.js\nmain();\n",
+        fs::read_to_string(&test).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_write_keeps_permissions_and_symlinks() {
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    let temp_dir = TempDir::new().unwrap();
+    let script = create_test_file(temp_dir.path(), "run.sh", "#!/bin/sh\necho hi\n");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+    let target = create_test_file(temp_dir.path(), "real/lib.js", "lib();\n");
+    symlink(&target, temp_dir.path().join("link.js")).unwrap();
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.process_file(&script).unwrap();
+    cli_arc
+        .process_file(&temp_dir.path().join("link.js"))
+        .unwrap();
+
+    assert_eq!(
+        "#!/bin/sh\n# run.sh\necho hi\n",
+        fs::read_to_string(&script).unwrap()
+    );
+    let mode = fs::metadata(&script).unwrap().permissions().mode();
+    assert_eq!(0o750, mode & 0o777);
+
+    // The symlink is left in place, the file it points to gets the header
+    let link_meta = fs::symlink_metadata(temp_dir.path().join("link.js")).unwrap();
+    assert!(link_meta.file_type().is_symlink());
+    assert_eq!("// link.js\nlib();\n", fs::read_to_string(&target).unwrap());
+}
+
+#[test]
+fn test_write_preserve_mtime() {
+    let temp_dir = TempDir::new().unwrap();
+    let file = create_test_file(temp_dir.path(), "old.js", "old();\n");
+    let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).preserve_mtime(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.process_file(&file).unwrap();
+
+    assert_eq!("// old.js\nold();\n", fs::read_to_string(&file).unwrap());
+    assert_eq!(mtime, fs::metadata(&file).unwrap().modified().unwrap());
+}
+
+#[test]
+fn test_write_read_only_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let locked = create_test_file(temp_dir.path(), "locked.js", "locked();\n");
+    let open = create_test_file(temp_dir.path(), "open.js", "open();\n");
+    let mut permissions = fs::metadata(&locked).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&locked, permissions).unwrap();
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let report = cli_arc.process_file(&locked).unwrap();
+    assert!(matches!(
+        report.outcome,
+        FileOutcome::Skipped(SkipReason::WriteError)
+    ));
+
+    // The other files are still processed
+    cli_arc.run();
+    assert_eq!("locked();\n", fs::read_to_string(&locked).unwrap());
+    assert_eq!("// open.js\nopen();\n", fs::read_to_string(&open).unwrap());
+}
+
 // Re-include tests that might have been implicitly removed or need slight adaptation
 #[test]
 fn test_relative_path_calculation_standard() {
@@ -635,5 +976,1571 @@ fn test_multiple_path_comments() {
     );
 }
 
-// Import the find_git_root function if it's not public or in scope
-use crate::find_git_root;
+#[test]
+fn test_shebang_preserved() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = create_test_file(
+        temp_dir.path(),
+        "deploy.sh",
+        "#!/usr/bin/env bash\necho hi\n",
+    );
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    let new_content = fs::read_to_string(&test_file).unwrap();
+    assert_eq!("#!/usr/bin/env bash\n# deploy.sh\necho hi\n", new_content);
+
+    // A second run recognises the header after the shebang and leaves the file alone
+    processor.process_file(&test_file).unwrap();
+    assert_eq!(new_content, fs::read_to_string(&test_file).unwrap());
+    let (processed, skipped) = processor.get_stats();
+    assert_eq!((processed, skipped), (1, 1));
+}
+
+#[test]
+fn test_shebang_stale_header_moved() {
+    let temp_dir = TempDir::new().unwrap();
+    // Stale header from before the file was moved sits below the shebang
+    let test_file = create_test_file(
+        temp_dir.path(),
+        "tool.py",
+        "#!/usr/bin/env python3\n# old/tool.py\nprint('hi')\n",
+    );
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    let new_content = fs::read_to_string(&test_file).unwrap();
+    assert_eq!(
+        "#!/usr/bin/env python3\n# tool.py\nprint('hi')\n",
+        new_content
+    );
+}
+
+#[test]
+fn test_header_above_shebang_moved_below() {
+    let temp_dir = TempDir::new().unwrap();
+    // A header written above the shebang breaks the script, so it must be moved
+    let test_file = create_test_file(
+        temp_dir.path(),
+        "s.sh",
+        "# s.sh\n#!/usr/bin/env bash\necho hi\n",
+    );
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    let new_content = fs::read_to_string(&test_file).unwrap();
+    assert_eq!("#!/usr/bin/env bash\n# s.sh\necho hi\n", new_content);
+
+    processor.process_file(&test_file).unwrap();
+    assert_eq!(new_content, fs::read_to_string(&test_file).unwrap());
+    let (processed, skipped) = processor.get_stats();
+    assert_eq!((processed, skipped), (1, 1));
+}
+
+#[test]
+fn test_shebang_only_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = create_test_file(temp_dir.path(), "run.sh", "#!/bin/sh\n");
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    let new_content = fs::read_to_string(&test_file).unwrap();
+    assert_eq!("#!/bin/sh\n# run.sh\n", new_content);
+}
+
+#[test]
+fn test_rust_inner_attribute_not_shebang() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "#![deny(missing_docs)]\n#![allow(dead_code)]\n//! Docs\n",
+    );
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    let new_content = fs::read_to_string(&test_file).unwrap();
+    assert_eq!(
+        "// lib.rs\n#![deny(missing_docs)]\n#![allow(dead_code)]\n//! Docs\n",
+        new_content
+    );
+}
+
+// --- Preamble Placement Tests ---
+
+// Runs the processor over a single file in a fresh temp dir and returns the new content
+fn process_single(name: &str, content: &str) -> String {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = create_test_file(temp_dir.path(), name, content);
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    fs::read_to_string(&test_file).unwrap()
+}
+
+#[test]
+fn test_preamble_xml_declaration() {
+    let new_content = process_single(
+        "pom.xml",
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<project/>\n",
+    );
+    assert_eq!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- pom.xml -->\n<project/>\n",
+        new_content
+    );
+}
+
+#[test]
+fn test_preamble_php_and_doctype() {
+    assert_eq!(
+        "<?php\n// index.php\necho 1;\n",
+        process_single("index.php", "<?php\necho 1;\n")
+    );
+    assert_eq!(
+        "<!doctype html>\n<!-- index.html -->\n<html></html>\n",
+        process_single("index.html", "<!doctype html>\n<html></html>\n")
+    );
+}
+
+#[test]
+fn test_preamble_header_above_declaration_moved() {
+    assert_eq!(
+        "<?xml version=\"1.0\"?>\n<!-- a.xml -->\n<a/>\n",
+        process_single("a.xml", "<!-- a.xml -->\n<?xml version=\"1.0\"?>\n<a/>\n")
+    );
+    assert_eq!(
+        "<?php\n// index.php\necho 1;\n",
+        process_single("index.php", "// index.php\n<?php\necho 1;\n")
+    );
+}
+
+#[test]
+fn test_check_mode_header_above_preamble_is_stale() {
+    let temp_dir = TempDir::new().unwrap();
+    let content = "<!-- a.xml -->\n<?xml version=\"1.0\"?>\n<a/>\n";
+    let test_file = create_test_file(temp_dir.path(), "a.xml", content);
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).check(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 1);
+    assert_eq!(content, fs::read_to_string(&test_file).unwrap());
+    assert_eq!(
+        cli_arc.check_failures(),
+        vec![("a.xml".to_string(), cli::CheckStatus::Stale)]
+    );
+}
+
+#[test]
+fn test_preamble_python_shebang_and_encoding() {
+    let new_content = process_single(
+        "tool.py",
+        "#!/usr/bin/env python\n# -*- coding: utf-8 -*-\nprint('hi')\n",
+    );
+    assert_eq!(
+        "#!/usr/bin/env python\n# -*- coding: utf-8 -*-\n# tool.py\nprint('hi')\n",
+        new_content
+    );
+}
+
+#[test]
+fn test_preamble_front_matter() {
+    let new_content = process_single("post.md", "---\ntitle: Hi\n---\n# Heading\n");
+    assert_eq!(
+        "---\ntitle: Hi\n---\n<!-- post.md -->\n# Heading\n",
+        new_content
+    );
+
+    // An unterminated block isn't front matter
+    let new_content = process_single("rule.md", "---\ntext\n");
+    assert_eq!("<!-- rule.md -->\n---\ntext\n", new_content);
+}
+
+#[test]
+fn test_preamble_utf8_bom() {
+    let new_content = process_single("lib.rs", "\u{feff}fn main() {}\n");
+    assert_eq!("\u{feff}// lib.rs\nfn main() {}\n", new_content);
+}
+
+#[test]
+fn test_preamble_custom_config() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "custom.cfg",
+        "js //\npreamble js line ^['\"]use strict['\"]\n",
+    );
+    let test_file = create_test_file(temp_dir.path(), "app.js", "'use strict';\nrun();\n");
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .config_file("custom.cfg")
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+
+    processor.process_file(&test_file).unwrap();
+    assert_eq!(
+        "'use strict';\n// app.js\nrun();\n",
+        fs::read_to_string(&test_file).unwrap()
+    );
+}
+
+#[test]
+fn test_check_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    let correct = create_test_file(temp_dir.path(), "ok.js", "// ok.js\ncontent();\n");
+    let missing = create_test_file(temp_dir.path(), "new.js", "content();\n");
+    let stale = create_test_file(temp_dir.path(), "src/old.js", "// old.js\ncontent();\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).check(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 1);
+
+    // Nothing is written in check mode
+    assert_eq!(
+        "// ok.js\ncontent();\n",
+        fs::read_to_string(&correct).unwrap()
+    );
+    assert_eq!("content();\n", fs::read_to_string(&missing).unwrap());
+    assert_eq!(
+        "// old.js\ncontent();\n",
+        fs::read_to_string(&stale).unwrap()
+    );
+
+    assert_eq!(
+        cli_arc.check_failures(),
+        vec![
+            ("new.js".to_string(), cli::CheckStatus::Missing),
+            ("src/old.js".to_string(), cli::CheckStatus::Stale),
+        ]
+    );
+    let (processed, _) = cli_arc.get_stats();
+    assert_eq!(processed, 0); // Would-be writes aren't counted as processed
+}
+
+#[test]
+fn test_check_mode_passes() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "ok.js", "// ok.js\ncontent();\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).check(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 0);
+    assert!(cli_arc.check_failures().is_empty());
+}
+
+#[test]
+fn test_explicit_file_list() {
+    let temp_dir = TempDir::new().unwrap();
+    let listed = create_test_file(temp_dir.path(), "src/a.js", "a();\n");
+    let unlisted = create_test_file(temp_dir.path(), "src/b.js", "b();\n");
+    let in_dir = create_test_file(temp_dir.path(), "lib/c.py", "c()\n");
+    // Explicit files bypass the directory skip list
+    let in_build = create_test_file(temp_dir.path(), "build/gen.js", "gen();\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["src/a.js", "lib", "build/gen.js"])
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 0);
+
+    assert_eq!("// src/a.js\na();\n", fs::read_to_string(&listed).unwrap());
+    assert_eq!("b();\n", fs::read_to_string(&unlisted).unwrap());
+    assert_eq!("# lib/c.py\nc()\n", fs::read_to_string(&in_dir).unwrap());
+    assert_eq!(
+        "// build/gen.js\ngen();\n",
+        fs::read_to_string(&in_build).unwrap()
+    );
+    let (processed, _) = cli_arc.get_stats();
+    assert_eq!(processed, 3);
+}
+
+#[test]
+fn test_hook_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    let missing = create_test_file(temp_dir.path(), "src/a.js", "a();\n");
+    create_test_file(temp_dir.path(), "src/ok.js", "// src/ok.js\nok();\n");
+    create_test_file(temp_dir.path(), "notes.xyz", "text\n");
+
+    // Files are fixed in place and the hook fails so they can be reviewed
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["src/a.js", "src/ok.js", "notes.xyz"])
+        .hook(true)
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 1);
+    assert_eq!("// src/a.js\na();\n", fs::read_to_string(&missing).unwrap());
+
+    // Once everything is fixed, it passes
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["src/a.js", "src/ok.js", "notes.xyz"])
+        .hook(true)
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    assert_eq!(cli_arc.run(), 0);
+}
+
+#[test]
+fn test_explicit_files_resolve_own_git_root() {
+    let temp_dir = TempDir::new().unwrap();
+    for repo in ["repo_a", "repo_b"] {
+        create_dir_all(temp_dir.path().join(repo).join(".git")).unwrap();
+    }
+    let file_a = create_test_file(temp_dir.path(), "repo_a/src/main.rs", "fn main() {}\n");
+    let file_b = create_test_file(temp_dir.path(), "repo_b/lib/util.rs", "fn util() {}\n");
+
+    let (args, _temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["repo_a/src/main.rs", "repo_b/lib/util.rs"])
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, temp_dir.path());
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!(
+        "// src/main.rs\nfn main() {}\n",
+        fs::read_to_string(&file_a).unwrap()
+    );
+    assert_eq!(
+        "// lib/util.rs\nfn util() {}\n",
+        fs::read_to_string(&file_b).unwrap()
+    );
+}
+
+#[test]
+fn test_apply_header_pure() {
+    let options = transform::HeaderOptions::default();
+    let update = transform::apply_header(
+        "// old/lib.rs\nfn main() {}\n",
+        "src/lib.rs",
+        Style::Slash,
+        &[],
+        options,
+    );
+    assert!(update.changed);
+    assert!(!update.had_header);
+    assert_eq!(update.header, "// src/lib.rs");
+    assert_eq!(update.stripped, vec!["// old/lib.rs".to_string()]);
+    assert_eq!(update.content, "// src/lib.rs\nfn main() {}\n");
+
+    // Applying it again is a no-op
+    let again = transform::apply_header(&update.content, "src/lib.rs", Style::Slash, &[], options);
+    assert!(!again.changed);
+    assert!(again.had_header);
+    assert_eq!(again.content, update.content);
+
+    // Clean mode removes it
+    let options = transform::HeaderOptions {
+        clean: true,
+        ..options
+    };
+    let cleaned =
+        transform::apply_header(&update.content, "src/lib.rs", Style::Slash, &[], options);
+    assert_eq!(cleaned.content, "fn main() {}\n");
+}
+
+#[test]
+fn test_crlf_line_endings() {
+    let temp_dir = TempDir::new().unwrap();
+    let file = create_test_file(
+        temp_dir.path(),
+        "win.js",
+        "// old/win.js\r\nfoo();\r\nbar();\r\n",
+    );
+    let no_trailing = create_test_file(temp_dir.path(), "last.js", "foo();\r\nbar();");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!(
+        "// win.js\r\nfoo();\r\nbar();\r\n",
+        fs::read_to_string(&file).unwrap()
+    );
+    assert_eq!(
+        "// last.js\r\nfoo();\r\nbar();",
+        fs::read_to_string(&no_trailing).unwrap()
+    );
+
+    let (processed, _) = cli_arc.get_stats();
+    assert_eq!(processed, 2);
+
+    // A correct header followed by CRLF is recognized
+    let update = transform::apply_header(
+        "// win.js\r\nfoo();\r\n",
+        "win.js",
+        Style::Slash,
+        &[],
+        transform::HeaderOptions::default(),
+    );
+    assert!(!update.changed);
+}
+
+#[test]
+fn test_lone_cr_line_endings() {
+    let config = Config::default();
+    let update = transform::apply_header(
+        "#!/bin/sh\r# old.sh\recho hi\r",
+        "run.sh",
+        Style::Hash,
+        &config.preambles_for(Path::new("run.sh")),
+        transform::HeaderOptions::default(),
+    );
+    assert_eq!(update.content, "#!/bin/sh\r# run.sh\recho hi\r");
+    assert_eq!(update.stripped, vec!["# old.sh".to_string()]);
+}
+
+#[test]
+fn test_mixed_line_endings() {
+    let options = transform::HeaderOptions::default();
+
+    // The header uses the dominant ending, every other line keeps its own
+    let content = "a();\r\nb();\nc();\r\nd();\r";
+    let update = transform::apply_header(content, "m.js", Style::Slash, &[], options);
+    assert_eq!(update.content, "// m.js\r\na();\r\nb();\nc();\r\nd();\r");
+
+    // Ties go to LF
+    let content = "a();\r\nb();\n";
+    let update = transform::apply_header(content, "m.js", Style::Slash, &[], options);
+    assert_eq!(update.content, "// m.js\na();\r\nb();\n");
+
+    // Stripping a comment only removes that line and its own ending
+    let content = "a();\n// old.js\r\nb();\n";
+    let update = transform::apply_header(content, "m.js", Style::Slash, &[], options);
+    assert_eq!(update.content, "// m.js\na();\nb();\n");
+}
+
+fn utf16le_with_bom(text: &str) -> Vec<u8> {
+    let mut bytes = vec![0xFF, 0xFE];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    bytes
+}
+
+#[test]
+fn test_utf16_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let cs = temp_dir.path().join("Program.cs");
+    fs::write(&cs, utf16le_with_bom("class Program {}\r\n")).unwrap();
+    let xml = temp_dir.path().join("App.xml");
+    let mut be_bytes = vec![0xFE, 0xFF];
+    be_bytes.extend(
+        "<?xml version=\"1.0\"?>\n<app/>\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes),
+    );
+    fs::write(&xml, be_bytes).unwrap();
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    cli_arc.run();
+
+    assert_eq!(
+        utf16le_with_bom("// Program.cs\r\nclass Program {}\r\n"),
+        fs::read(&cs).unwrap()
+    );
+    let mut expected = vec![0xFE, 0xFF];
+    expected.extend(
+        "<?xml version=\"1.0\"?>\n<!-- App.xml -->\n<app/>\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes),
+    );
+    assert_eq!(expected, fs::read(&xml).unwrap());
+}
+
+#[test]
+fn test_configured_encodings() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "custom.cfg",
+        "sql --\ncs //\njs //\nencoding latin1 sql old_*.js\nencoding utf-16le legacy/**/*.cs\n",
+    );
+    // "café" in Latin-1, which isn't valid UTF-8
+    let sql = temp_dir.path().join("init.sql");
+    fs::write(&sql, b"SELECT 'caf\xe9';\n").unwrap();
+    // UTF-16LE without a BOM, only readable thanks to the glob rule
+    let legacy = temp_dir.path().join("legacy/Old.cs");
+    create_dir_all(legacy.parent().unwrap()).unwrap();
+    let legacy_bytes: Vec<u8> = "old();\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    fs::write(&legacy, &legacy_bytes).unwrap();
+    let other = temp_dir.path().join("other.js");
+    fs::write(&other, b"caf\xe9();\n").unwrap();
+    // Globs without a slash match the file name in any directory
+    let nested = temp_dir.path().join("src/lib/old_app.js");
+    create_dir_all(nested.parent().unwrap()).unwrap();
+    fs::write(&nested, b"caf\xe9();\n").unwrap();
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .config_file("custom.cfg")
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let reports = cli_arc.collect_reports();
+
+    assert_eq!(
+        b"-- init.sql\nSELECT 'caf\xe9';\n".to_vec(),
+        fs::read(&sql).unwrap()
+    );
+    let expected: Vec<u8> = "// legacy/Old.cs\nold();\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    assert_eq!(expected, fs::read(&legacy).unwrap());
+    assert_eq!(
+        b"// src/lib/old_app.js\ncaf\xe9();\n".to_vec(),
+        fs::read(&nested).unwrap()
+    );
+
+    // Without a rule, Latin-1 is read as UTF-8 and isn't valid
+    assert_eq!(b"caf\xe9();\n".to_vec(), fs::read(&other).unwrap());
+    let other_report = reports
+        .iter()
+        .find(|report| report.path.ends_with("other.js"))
+        .unwrap();
+    assert!(matches!(
+        other_report.outcome,
+        FileOutcome::Skipped(SkipReason::NonUtf8)
+    ));
+}
+
+#[test]
+fn test_unified_diff() {
+    let diff = transform::unified_diff("a();\nb();", "// src/a.js\na();\nb();", "src/a.js");
+    assert_eq!(
+        diff,
+        "--- a/src/a.js\n+++ b/src/a.js\n@@ -1,2 +1,3 @@\n+// src/a.js\n a();\n b();\n\\ No newline at end of file\n"
+    );
+}
+
+#[test]
+fn test_diff_dry_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let changed = create_test_file(temp_dir.path(), "src/a.js", "// a.js\na();\n");
+    create_test_file(temp_dir.path(), "src/ok.js", "// src/ok.js\nok();\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .diff(true)
+        .dry_run(true)
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let reports = cli_arc.collect_reports();
+
+    assert_eq!("// a.js\na();\n", fs::read_to_string(&changed).unwrap());
+    let diffs: Vec<&str> = reports
+        .iter()
+        .filter_map(|report| match &report.outcome {
+            FileOutcome::Processed { diff, .. } => diff.as_deref(),
+            FileOutcome::Skipped(_) => None,
+        })
+        .collect();
+    // Unchanged files get no diff
+    assert_eq!(
+        diffs,
+        vec!["--- a/src/a.js\n+++ b/src/a.js\n@@ -1,2 +1,2 @@\n-// a.js\n+// src/a.js\n a();\n"]
+    );
+}
+
+#[test]
+fn test_header_path() {
+    let base = Path::new("/repo");
+    assert_eq!(
+        transform::header_path(Path::new("/repo/src/main.rs"), base),
+        "src/main.rs"
+    );
+    assert_eq!(
+        transform::header_path(Path::new("/elsewhere/main.rs"), base),
+        "/elsewhere/main.rs"
+    );
+}
+
+#[test]
+fn test_config_apply() {
+    let config = Config::default();
+    assert_eq!(config.style_for(Path::new("a/b.py")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new("a/b.xyz")), None);
+
+    let update = config
+        .apply(
+            "<?php\necho 1;\n",
+            Path::new("/repo/web/index.php"),
+            Path::new("/repo"),
+            transform::HeaderOptions::default(),
+        )
+        .unwrap();
+    assert_eq!(update.content, "<?php\n// web/index.php\necho 1;\n");
+    assert!(
+        config
+            .apply(
+                "",
+                Path::new("/repo/x.xyz"),
+                Path::new("/repo"),
+                transform::HeaderOptions::default()
+            )
+            .is_none()
+    );
+
+    // A parsed config replaces the extension styles but keeps the default preambles
+    let config = Config::parse("php #\n");
+    assert_eq!(config.style_for(Path::new("index.php")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new("main.rs")), None);
+    assert_eq!(config.preambles_for(Path::new("index.php")).len(), 2);
+}
+
+#[test]
+fn test_collect_reports() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "a.js", "a();\n");
+    create_test_file(temp_dir.path(), "b.js", "// b.js\nb();\n");
+    create_test_file(temp_dir.path(), "notes.xyz", "text\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).dry_run(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+
+    let mut reports = cli_arc.collect_reports();
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(reports.len(), 3);
+
+    let update = reports[0].update().unwrap();
+    assert!(update.changed);
+    assert_eq!(update.content, "// a.js\na();\n");
+    assert!(!reports[1].update().unwrap().changed);
+    assert!(matches!(
+        reports[2].outcome,
+        FileOutcome::Skipped(SkipReason::NoStyle)
+    ));
+
+    // Dry run, nothing written
+    assert_eq!(
+        "a();\n",
+        fs::read_to_string(temp_dir.path().join("a.js")).unwrap()
+    );
+}
+
+#[test]
+fn test_report_json() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "added.js", "a();\n");
+    create_test_file(temp_dir.path(), "updated.js", "// old.js\nu();\n");
+    create_test_file(temp_dir.path(), "ok.js", "// ok.js\nok();\n");
+    create_test_file(temp_dir.path(), "notes.xyz", "text\n");
+    create_test_file(temp_dir.path(), "node_modules/pkg/index.js", "pkg();\n");
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).dry_run(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let mut records: Vec<serde_json::Value> = cli_arc
+        .collect_reports()
+        .iter()
+        .map(|report| report.to_json())
+        .collect();
+    records.sort_by_key(|record| record["path"].as_str().unwrap().to_string());
+    for record in &mut records {
+        let path = record["path"].as_str().unwrap();
+        let name = path.rsplit('/').next().unwrap().to_string();
+        record["path"] = name.into();
+    }
+
+    assert_eq!(
+        records,
+        vec![
+            serde_json::json!({"type": "file", "path": "added.js", "style": "slash", "action": "added",
+                "reason": null, "old_header": null, "new_header": "// added.js", "diff": null, "renamed_from": null}),
+            serde_json::json!({"type": "file", "path": "node_modules", "style": null, "action": "skipped",
+                "reason": "ignored-dir", "old_header": null, "new_header": null, "diff": null, "renamed_from": null}),
+            serde_json::json!({"type": "file", "path": "notes.xyz", "style": null, "action": "skipped",
+                "reason": "no-style", "old_header": null, "new_header": null, "diff": null, "renamed_from": null}),
+            serde_json::json!({"type": "file", "path": "ok.js", "style": "slash", "action": "unchanged",
+                "reason": null, "old_header": "// ok.js", "new_header": "// ok.js", "diff": null, "renamed_from": null}),
+            serde_json::json!({"type": "file", "path": "updated.js", "style": "slash", "action": "updated",
+                "reason": null, "old_header": "// old.js", "new_header": "// updated.js", "diff": null, "renamed_from": null}),
+        ]
+    );
+}
+
+#[test]
+fn test_header_action_removed() {
+    let options = transform::HeaderOptions {
+        clean: true,
+        ..Default::default()
+    };
+    let update = transform::apply_header("// a.js\na();\n", "a.js", Style::Slash, &[], options);
+    assert_eq!(update.action, transform::HeaderAction::Removed);
+    assert_eq!(update.content, "a();\n");
+}
+
+#[test]
+fn test_color_choice() {
+    let plain = reporter::Palette { color: false };
+    assert_eq!(plain.added("// a.js"), "+ // a.js");
+    assert_eq!(plain.removed("// a.js"), "- // a.js");
+    assert_eq!(plain.no_change("(no change)"), " (no change)");
+
+    let colored = reporter::Palette { color: true };
+    assert_eq!(colored.added("// a.js"), "\x1b[32m+ // a.js\x1b[0m");
+
+    assert!(reporter::use_color(ColorChoice::Always));
+    assert!(!reporter::use_color(ColorChoice::Never));
+}
+
+#[test]
+fn test_report_error_stops_walk() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let temp_dir = TempDir::new().unwrap();
+    for i in 0..50 {
+        create_test_file(temp_dir.path(), &format!("f{i}.js"), "x();\n");
+    }
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .dry_run(true)
+        .threads(2)
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+
+    // Like stdout closed by `| head -1`: every worker stops after its first failed report
+    let calls = AtomicUsize::new(0);
+    let result = cli_arc.process_paths(&|_| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    });
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+    assert!(calls.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn test_sorted_reporter() {
+    use reporter::Reporter;
+    use std::sync::{Arc, Mutex};
+
+    // Records the order reports are passed on in
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+    impl Reporter for Recorder {
+        fn report(&self, report: &crate::FileReport) -> std::io::Result<()> {
+            let name = report.path.file_name().unwrap().to_string_lossy();
+            self.0.lock().unwrap().push(name.to_string());
+            Ok(())
+        }
+        fn finish(&self, _summary: &reporter::Summary) -> std::io::Result<()> {
+            self.0.lock().unwrap().push("summary".to_string());
+            Ok(())
+        }
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let names = ["e.js", "a/d.js", "b.js", "a/c.js", "f.js"];
+    for name in names {
+        create_test_file(temp_dir.path(), name, "x();\n");
+    }
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .dry_run(true)
+        .threads(4)
+        .build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let sorted = reporter::SortedReporter::new(Box::new(Recorder(order.clone())));
+    cli_arc
+        .process_paths(&|report| sorted.report(report))
+        .unwrap();
+    // Nothing is passed on before the end
+    assert!(order.lock().unwrap().is_empty());
+
+    sorted
+        .finish(&reporter::Summary {
+            processed: 5,
+            skipped: 0,
+            dry_run: true,
+        })
+        .unwrap();
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["c.js", "d.js", "b.js", "e.js", "f.js", "summary"]
+    );
+}
+
+// Runs git in `dir`, panicking if it fails
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed");
+}
+
+// A repository with a committed file, then a staged, a modified and an untracked one
+fn create_git_selector_repo(temp_dir: &TempDir) {
+    let root = temp_dir.path();
+    git(root, &["init", "-q"]);
+    create_test_file(root, "committed.js", "committed();\n");
+    create_test_file(root, "modified.js", "modified();\n");
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+
+    create_test_file(root, "src/staged.js", "staged();\n");
+    git(root, &["add", "src/staged.js"]);
+    create_test_file(root, "modified.js", "modified(1);\n");
+    create_test_file(root, "untracked.js", "untracked();\n");
+}
+
+fn git_selected_files(args: Args, temp_path: &Path) -> Vec<String> {
+    let (base_dir, gitignore_path) = determine_test_paths(&args, temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let mut files: Vec<String> = cli_arc
+        .collect_reports()
+        .iter()
+        .map(|report| report.path.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_git_selectors() {
+    let temp_dir = TempDir::new().unwrap();
+    create_git_selector_repo(&temp_dir);
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .git_staged(true)
+        .dry_run(true)
+        .build();
+    assert_eq!(git_selected_files(args, &temp_path), vec!["src/staged.js"]);
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .git_changed("HEAD")
+        .dry_run(true)
+        .build();
+    assert_eq!(
+        git_selected_files(args, &temp_path),
+        vec!["modified.js", "src/staged.js"]
+    );
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .git_tracked(true)
+        .dry_run(true)
+        .build();
+    assert_eq!(
+        git_selected_files(args, &temp_path),
+        vec!["committed.js", "modified.js", "src/staged.js"]
+    );
+}
+
+#[test]
+fn test_git_selector_restricted_to_paths() {
+    let temp_dir = TempDir::new().unwrap();
+    create_git_selector_repo(&temp_dir);
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .paths(&["src"])
+        .git_tracked(true)
+        .build();
+    assert_eq!(git_selected_files(args, &temp_path), vec!["src/staged.js"]);
+    assert_eq!(
+        "// src/staged.js\nstaged();\n",
+        fs::read_to_string(temp_dir.path().join("src/staged.js")).unwrap()
+    );
+    assert_eq!(
+        "committed();\n",
+        fs::read_to_string(temp_dir.path().join("committed.js")).unwrap()
+    );
+}
+
+#[test]
+fn test_git_renamed() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    git(root, &["init", "-q"]);
+    create_test_file(root, "old/name.js", "// old/name.js\nmoved();\n");
+    let stale = create_test_file(root, "stale.js", "// wrong.js\nstale();\n");
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "initial"]);
+    git(root, &["mv", "old/name.js", "new.js"]);
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).git_renamed(None).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let reports = cli_arc.collect_reports();
+
+    // Only the renamed file is touched, and both paths are relative like the header
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].path, Path::new("new.js"));
+    assert_eq!(
+        reports[0].renamed_from.as_deref(),
+        Some(Path::new("old/name.js"))
+    );
+    let root = root.canonicalize().unwrap();
+    assert_eq!(
+        "// new.js\nmoved();\n",
+        fs::read_to_string(root.join("new.js")).unwrap()
+    );
+    assert_eq!(
+        "// wrong.js\nstale();\n",
+        fs::read_to_string(&stale).unwrap()
+    );
+
+    // Renames in a range of commits are found too
+    git(&root, &["commit", "-q", "-m", "rename"]);
+    git(&root, &["mv", "new.js", "newer.js"]);
+    git(&root, &["commit", "-q", "-m", "rename again"]);
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .git_renamed(Some("HEAD~1..HEAD"))
+        .dry_run(true)
+        .build();
+    assert_eq!(git_selected_files(args, &temp_path), vec!["newer.js"]);
+}
+
+// Import the find_git_root function if it's not public or in scope
+use crate::find_git_root;
+
+#[test]
+fn test_header_template() {
+    let template = transform::HeaderTemplate::parse("File: {path}").unwrap();
+    assert!(transform::HeaderTemplate::parse("File").is_none());
+    let options = transform::HeaderOptions {
+        template: Some(&template),
+        ..Default::default()
+    };
+
+    // Both plain path comments and stale templated ones are replaced
+    for content in [
+        "// old/lib.rs\nfn f() {}\n",
+        "// File: old/lib.rs\nfn f() {}\n",
+    ] {
+        let update = transform::apply_header(content, "src/lib.rs", Style::Slash, &[], options);
+        assert_eq!(update.content, "// File: src/lib.rs\nfn f() {}\n");
+        assert_eq!(update.action, transform::HeaderAction::Updated);
+    }
+
+    let update = transform::apply_header(
+        "<!-- File: index.html -->\n<p></p>\n",
+        "index.html",
+        Style::Xml,
+        &[],
+        options,
+    );
+    assert!(!update.changed);
+}
+
+#[test]
+fn test_project_config_hierarchy() {
+    let temp_dir = TempDir::new().unwrap();
+    create_dir_all(temp_dir.path().join(".git")).unwrap();
+    create_test_file(
+        temp_dir.path(),
+        project::PROJECT_CONFIG_FILENAME,
+        "header = \"File: {path}\"\nignore = [\"*.gen.rs\"]\n\n[styles]\nfoo = \"#\"\n\".bar\" = \"#\"\n\n[names]\nTaskfile = \"#\"\n",
+    );
+    // Nearer files override farther ones, and their ignore patterns are relative to them
+    create_test_file(
+        temp_dir.path(),
+        "app/.path-comment.toml",
+        "ignore = [\"/vendor/\"]\n\n[styles]\nfoo = \"slash\"\n",
+    );
+    let main = create_test_file(
+        temp_dir.path(),
+        "app/main.rs",
+        "// app/old.rs\nfn main() {}\n",
+    );
+    let foo = create_test_file(temp_dir.path(), "app/a.foo", "a\n");
+    let bar = create_test_file(temp_dir.path(), "app/b.bar", "b\n");
+    let taskfile = create_test_file(temp_dir.path(), "app/Taskfile", "t\n");
+    let generated = create_test_file(temp_dir.path(), "app/api.gen.rs", "fn api() {}\n");
+    let vendored = create_test_file(temp_dir.path(), "app/vendor/lib.rs", "fn lib() {}\n");
+
+    let (mut args, temp_path) = TestArgsBuilder::new(&temp_dir).dir("app").build();
+    project::apply(&mut args);
+    assert_eq!(args.project.files.len(), 2);
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    // Styles are merged over the defaults instead of replacing them
+    assert_eq!(
+        cli_arc.determine_comment_style(Path::new("x.py")),
+        Some(Style::Hash)
+    );
+    cli_arc.run();
+
+    assert_eq!(
+        "// File: app/main.rs\nfn main() {}\n",
+        fs::read_to_string(&main).unwrap()
+    );
+    assert_eq!("// File: app/a.foo\na\n", fs::read_to_string(&foo).unwrap());
+    assert_eq!("# File: app/b.bar\nb\n", fs::read_to_string(&bar).unwrap());
+    assert_eq!(
+        "# File: app/Taskfile\nt\n",
+        fs::read_to_string(&taskfile).unwrap()
+    );
+    assert_eq!("fn api() {}\n", fs::read_to_string(&generated).unwrap());
+    assert_eq!("fn lib() {}\n", fs::read_to_string(&vendored).unwrap());
+}
+
+#[test]
+fn test_project_config_flags_and_base() {
+    let temp_dir = TempDir::new().unwrap();
+    create_dir_all(temp_dir.path().join(".git")).unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "app/.path-comment.toml",
+        "base = \".\"\nkeep = true\nexclude = [\"tmp/**\"]\n",
+    );
+    // Invalid files are skipped with a warning
+    create_test_file(temp_dir.path(), ".path-comment.toml", "unknown-key = 1\n");
+    let main = create_test_file(
+        temp_dir.path(),
+        "app/src/main.rs",
+        "fn main() {}\n// other/file.rs\n",
+    );
+    let scratch = create_test_file(temp_dir.path(), "app/tmp/scratch.rs", "fn s() {}\n");
+
+    let (mut args, _) = TestArgsBuilder::new(&temp_dir).dir("app").build();
+    project::apply(&mut args);
+    assert_eq!(args.project.files.len(), 1);
+    assert!(args.keep);
+    let base = cli::resolve_base_dir(&args).unwrap();
+    assert_eq!(
+        base.dir,
+        temp_dir.path().join("app").canonicalize().unwrap()
+    );
+    assert!(!base.from_git);
+    cli::Cli::new_arc(args, base.dir, base.gitignore_path).run();
+
+    assert_eq!(
+        "// src/main.rs\nfn main() {}\n// other/file.rs\n",
+        fs::read_to_string(&main).unwrap()
+    );
+    assert_eq!("fn s() {}\n", fs::read_to_string(&scratch).unwrap());
+
+    // An explicit --base wins over the config's base policy
+    let (mut args, _) = TestArgsBuilder::new(&temp_dir)
+        .dir("app")
+        .base(&temp_dir.path().to_string_lossy())
+        .build();
+    project::apply(&mut args);
+    assert_eq!(
+        args.base.as_deref(),
+        Some(&*temp_dir.path().to_string_lossy())
+    );
+
+    // So does --no-keep over `keep = true`, and the last of --keep and --no-keep is used
+    let (mut args, _) = TestArgsBuilder::new(&temp_dir).dir("app").build();
+    args.no_keep = true;
+    project::apply(&mut args);
+    assert!(!args.keep);
+    let dir = temp_dir.path().join("app").to_string_lossy().into_owned();
+    let args = Args::try_parse_from(["path-comment", "--no-keep", "--keep", &dir]).unwrap();
+    assert!(args.keep && !args.no_keep);
+    let args = Args::try_parse_from(["path-comment", "--keep", "--no-keep", &dir]).unwrap();
+    assert!(!args.keep && args.no_keep);
+}
+
+#[test]
+fn test_filename_rules() {
+    let config = Config::default();
+    for name in [
+        "Makefile",
+        "sub/.bashrc",
+        "Dockerfile",
+        "docker/Dockerfile.dev",
+        "CMakeLists.txt",
+        "BUILD",
+    ] {
+        assert_eq!(
+            config.style_for(Path::new(name)),
+            Some(Style::Hash),
+            "{name}"
+        );
+    }
+    assert_eq!(
+        config.style_for(Path::new("types/index.d.ts")),
+        Some(Style::Slash)
+    );
+    assert_eq!(config.style_for(Path::new("LICENSE")), None);
+    // Exact names only match the whole file name, and extensions never match file names
+    for name in ["foo.makefile", "x.bashrc", "c", "go", "sh"] {
+        assert_eq!(config.style_for(Path::new(name)), None, "{name}");
+    }
+
+    // Exact names win over globs, which win over extensions, and later globs win
+    let config = Config::parse("js //\n*.gen.js #\n*.js ;\nspecial.gen.js --\n");
+    assert_eq!(config.style_for(Path::new("a.js")), Some(Style::Semi));
+    assert_eq!(config.style_for(Path::new("a.gen.js")), Some(Style::Semi));
+    assert_eq!(
+        config.style_for(Path::new("src/special.gen.js")),
+        Some(Style::DoubleDash)
+    );
+    let config = Config::parse("js //\n*.gen.js #\n");
+    assert_eq!(config.style_for(Path::new("a.js")), Some(Style::Slash));
+    assert_eq!(config.style_for(Path::new("a.GEN.js")), Some(Style::Hash));
+
+    // Older configs wrote extensions with a leading dot or in uppercase, exact names need the
+    // name directive unless they contain a dot
+    let config = Config::parse(".rs //\nR #\nname Justfile #\n.env.local #\n");
+    assert_eq!(
+        config.style_for(Path::new("src/main.rs")),
+        Some(Style::Slash)
+    );
+    assert_eq!(config.style_for(Path::new("plot.r")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new("plot.R")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new("justfile")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new(".env.local")), Some(Style::Hash));
+    assert_eq!(config.style_for(Path::new(".rs")), None);
+
+    let temp_dir = TempDir::new().unwrap();
+    let makefile = create_test_file(temp_dir.path(), "Makefile", "all:\n");
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    cli::Cli::new_arc(args, base_dir, gitignore_path).run();
+    assert_eq!("# Makefile\nall:\n", fs::read_to_string(&makefile).unwrap());
+
+    // Walks pick up dotfiles with a file name rule, but no other hidden files or directories
+    let temp_dir = TempDir::new().unwrap();
+    let bashrc = create_test_file(temp_dir.path(), ".bashrc", "alias ll='ls -l'\n");
+    let hidden = create_test_file(temp_dir.path(), ".hidden.rs", "fn main() {}\n");
+    let nested = create_test_file(temp_dir.path(), ".config/app.rs", "fn main() {}\n");
+    for force in [false, true] {
+        let (args, temp_path) = TestArgsBuilder::new(&temp_dir).force(force).build();
+        let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+        cli::Cli::new_arc(args, base_dir, gitignore_path).run();
+        assert_eq!(
+            "# .bashrc\nalias ll='ls -l'\n",
+            fs::read_to_string(&bashrc).unwrap()
+        );
+        assert_eq!("fn main() {}\n", fs::read_to_string(&hidden).unwrap());
+        assert_eq!("fn main() {}\n", fs::read_to_string(&nested).unwrap());
+    }
+}
+
+#[test]
+fn test_sniff_detection() {
+    let config = Config::default();
+    for (content, style) in [
+        ("#!/usr/bin/env python3\nprint()\n", Some(Style::Hash)),
+        ("#!/usr/bin/env -S node --harmony\n", Some(Style::Slash)),
+        ("#!/usr/bin/python3.12 -u\n", Some(Style::Hash)),
+        ("#!/bin/sh\n", Some(Style::Hash)),
+        ("\u{feff}#!/usr/bin/env lua\n", Some(Style::DoubleDash)),
+        ("#!/usr/bin/env unknown\n", None),
+        (
+            "local x = 1\n-- vim: set ft=lua:\n",
+            Some(Style::DoubleDash),
+        ),
+        ("# vim: filetype=yaml\nkey: value\n", Some(Style::Hash)),
+        (
+            "; -*- mode: emacs-lisp; lexical-binding: t -*-\n",
+            Some(Style::Semi),
+        ),
+        ("#!/bin/false\n# -*- ruby -*-\n", Some(Style::Hash)),
+        ("plain text\n", None),
+    ] {
+        assert_eq!(config.sniff_style(content), style, "{content:?}");
+    }
+
+    let config = Config::parse("interpreter mytool //\n");
+    assert_eq!(
+        config.sniff_style("#!/usr/bin/env mytool\n"),
+        Some(Style::Slash)
+    );
+    assert_eq!(config.sniff_style("#!/scripts/bash\n"), Some(Style::Hash));
+}
+
+#[test]
+fn test_sniff_flag() {
+    let temp_dir = TempDir::new().unwrap();
+    let deploy = create_test_file(
+        temp_dir.path(),
+        "scripts/deploy",
+        "#!/usr/scripts/env python3\nprint()\n",
+    );
+    let notes = create_test_file(temp_dir.path(), "scripts/notes", "plain text\n");
+    let binary = temp_dir.path().join("scripts/blob");
+    fs::write(&binary, [0xff, 0xfe, 0x00, 0x01]).unwrap();
+    // Large files are only sniffed at both ends, so a modeline at the end is still found
+    let long_script = format!("{}# vim: set ft=python:\n", "pass\n".repeat(10_000));
+    let long = create_test_file(temp_dir.path(), "scripts/long", &long_script);
+    let mut elf = b"\x7fELF\x02\x01\x01\x00".to_vec();
+    elf.resize(100_000, b'#');
+    fs::write(temp_dir.path().join("scripts/elf"), elf).unwrap();
+
+    // Sniffing is opt-in
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    cli::Cli::new_arc(args, base_dir, gitignore_path).run();
+    assert_eq!(
+        "#!/usr/scripts/env python3\nprint()\n",
+        fs::read_to_string(&deploy).unwrap()
+    );
+
+    let (args, temp_path) = TestArgsBuilder::new(&temp_dir).sniff(true).build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let cli_arc = cli::Cli::new_arc(args, base_dir, gitignore_path);
+    let mut reports = cli_arc.collect_reports();
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+    // Binary and unrecognized files are skipped as if they had no extension to match
+    let skipped: Vec<_> = reports
+        .iter()
+        .filter(|report| matches!(report.outcome, FileOutcome::Skipped(SkipReason::NoStyle)))
+        .map(|report| report.path.file_name().unwrap())
+        .collect();
+    assert_eq!(skipped, ["blob", "elf", "notes"]);
+    assert_eq!(
+        format!("# scripts/long\n{long_script}"),
+        fs::read_to_string(&long).unwrap()
+    );
+    assert_eq!(
+        "#!/usr/scripts/env python3\n# scripts/deploy\nprint()\n",
+        fs::read_to_string(&deploy).unwrap()
+    );
+    assert_eq!("plain text\n", fs::read_to_string(&notes).unwrap());
+}
+
+#[test]
+fn test_all_comment_styles() {
+    for style in Style::BUILTIN {
+        let (start, end) = style.delimiters();
+        // Every style can be given by its delimiters in the config file, or by its name
+        let config_str = format!("{} {}", start.trim(), end.trim());
+        assert_eq!(Style::parse(&config_str), Some(style), "{config_str}");
+        assert_eq!(Style::parse(style.name()), Some(style));
+
+        let options = transform::HeaderOptions::default();
+        let added = transform::apply_header("x\n", "src/a.ext", style, &[], options);
+        assert_eq!(added.content, format!("{start}src/a.ext{end}\nx\n"));
+
+        // The detection regex recognizes the header, so a stale one is replaced
+        let moved = transform::apply_header(&added.content, "lib/a.ext", style, &[], options);
+        assert_eq!(moved.content, format!("{start}lib/a.ext{end}\nx\n"));
+        assert_eq!(moved.stripped, [format!("{start}src/a.ext{end}")]);
+    }
+
+    let config = Config::default();
+    for (name, style) in [
+        ("a.ml", Style::ParenStar),
+        ("a.bat", Style::Rem),
+        ("a.vb", Style::Apostrophe),
+        ("a.f90", Style::Bang),
+        (".vimrc", Style::Quote),
+        ("_vimrc", Style::Quote),
+        ("a.j2", Style::BraceHash),
+        ("a.hbs", Style::Handlebars),
+        ("a.erb", Style::Erb),
+        ("a.cshtml", Style::Razor),
+        ("a.erl", Style::Percent),
+        ("a.escript", Style::Percent),
+        ("a.m", Style::Percent),
+    ] {
+        assert_eq!(config.style_for(Path::new(name)), Some(style), "{name}");
+    }
+    assert_eq!(
+        config.sniff_style("#!/usr/bin/env escript\nmain(_) -> ok.\n"),
+        Some(Style::Percent)
+    );
+
+    // Existing `%` headers are recognized instead of getting a second header, and MATLAB
+    // headers don't start a `%%` code section
+    for (name, content) in [
+        ("a.erl", "% src/a.erl\n-module(a).\n"),
+        ("a.m", "% old/a.m\nx = 1;\n"),
+    ] {
+        let update = config
+            .apply(
+                content,
+                Path::new(name),
+                Path::new(""),
+                transform::HeaderOptions::default(),
+            )
+            .unwrap();
+        let body = content.split_once('\n').unwrap().1;
+        assert_eq!(update.content, format!("% {name}\n{body}"), "{name}");
+    }
+
+    // Batch files would echo a header above `@echo off`
+    for name in ["run.bat", "run.CMD"] {
+        let update = config
+            .apply(
+                "@ECHO OFF\r\necho hi\r\n",
+                Path::new(name),
+                Path::new(""),
+                transform::HeaderOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            update.content,
+            format!("@ECHO OFF\r\nREM {name}\r\necho hi\r\n")
+        );
+    }
+
+    // A docstring header would replace the module's own docstring, so such files are skipped
+    let preambles = config.preambles_for(Path::new("a.py"));
+    for (content, expected) in [
+        ("\"\"\"Module doc.\"\"\"\nimport os\n", true),
+        ("#!/usr/bin/env python3\n# A comment\n\nr'''Doc'''\n", true),
+        ("\"\"\"src/a.py\"\"\"\n\"\"\"Module doc.\"\"\"\n", false),
+        ("import os\n\"\"\"Not a docstring.\"\"\"\n", false),
+        ("", false),
+    ] {
+        assert_eq!(
+            transform::has_docstring(content, &preambles),
+            expected,
+            "{content}"
+        );
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let documented = create_test_file(temp_dir.path(), "d.py", "\"\"\"Module doc.\"\"\"\n");
+    let plain = create_test_file(temp_dir.path(), "p.py", "import os\n");
+    let mut builder = TestArgsBuilder::new(&temp_dir);
+    builder.args.comment_style = Some("docstring".to_string());
+    let (args, temp_path) = builder.build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let reports = cli::Cli::new_arc(args, base_dir, gitignore_path).collect_reports();
+    let documented_report = reports
+        .iter()
+        .find(|report| report.path == Path::new("d.py"))
+        .unwrap();
+    assert!(matches!(
+        documented_report.outcome,
+        FileOutcome::Skipped(SkipReason::Docstring)
+    ));
+    assert_eq!(
+        "\"\"\"Module doc.\"\"\"\n",
+        fs::read_to_string(&documented).unwrap()
+    );
+    assert_eq!(
+        "\"\"\"p.py\"\"\"\nimport os\n",
+        fs::read_to_string(&plain).unwrap()
+    );
+}
+
+#[test]
+fn test_custom_comment_styles() {
+    let config = Config::parse(concat!(
+        "adoc asciidoc\n",
+        "style asciidoc \"//\" \"\"\n",
+        "style banner \"<<< \" \" >>>\"\n",
+        "style wiki \"%%\\\"\"\n",
+        "bnr banner\n",
+        "wk wiki\n",
+        "rs //\n",
+        // Built-in names can't be redefined, and unknown styles are still rejected
+        "style slash \"##\"\n",
+        "xyz nope\n",
+    ));
+    let asciidoc = config.style_for(Path::new("doc.adoc")).unwrap();
+    let banner = config.style_for(Path::new("a.bnr")).unwrap();
+    assert_eq!(asciidoc.name(), "asciidoc");
+    assert_eq!(asciidoc.delimiters(), ("// ", ""));
+    assert_eq!(banner.delimiters(), ("<<< ", " >>>"));
+    assert_eq!(
+        config.style_for(Path::new("a.wk")).unwrap().delimiters(),
+        ("%%\" ", "")
+    );
+    assert_eq!(config.style_for(Path::new("a.rs")), Some(Style::Slash));
+    assert_eq!(
+        config.extension_styles.parse_style("slash"),
+        Some(Style::Slash)
+    );
+    assert_eq!(config.style_for(Path::new("a.xyz")), None);
+    // Parsing the same definition again gives the same style
+    assert_eq!(
+        Config::parse("style banner \"<<<\" \">>>\"\nbnr banner\n").style_for(Path::new("a.bnr")),
+        Some(banner)
+    );
+
+    // Custom styles are detected and stripped like the built-in ones
+    let update = transform::apply_header(
+        "<<< old.bnr >>>\nbody\n<<< other/file.bnr >>>\n",
+        "src/a.bnr",
+        banner,
+        &[],
+        transform::HeaderOptions::default(),
+    );
+    assert_eq!(update.content, "<<< src/a.bnr >>>\nbody\n");
+
+    // --comment-style accepts custom names once the config is loaded
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "custom.cfg",
+        "style banner \"<<<\" \">>>\"\nrs //\n",
+    );
+    let file = create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let mut builder = TestArgsBuilder::new(&temp_dir).config_file("custom.cfg");
+    builder.args.comment_style = Some("banner".to_string());
+    let (args, temp_path) = builder.build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    assert_eq!(cli::Cli::new_arc(args, base_dir, gitignore_path).run(), 0);
+    assert_eq!(
+        "<<< main.rs >>>\nfn main() {}\n",
+        fs::read_to_string(&file).unwrap()
+    );
+
+    let mut builder = TestArgsBuilder::new(&temp_dir);
+    builder.args.comment_style = Some("banner".to_string());
+    let (args, temp_path) = builder.build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    assert_eq!(cli::Cli::new_arc(args, base_dir, gitignore_path).run(), 1);
+}
+
+#[test]
+fn test_multi_language_files() {
+    let config = Config::default();
+    let apply = |name: &str, content: &str| {
+        config
+            .apply(
+                content,
+                Path::new(name),
+                Path::new(""),
+                transform::HeaderOptions::default(),
+            )
+            .unwrap()
+            .content
+    };
+
+    // Components get the style of the block they open with
+    let script_first =
+        "<script setup>\nconst a = 1\n</script>\n\n<template>\n  <div/>\n</template>\n";
+    let with_header =
+        "<script setup>\n// a.vue\nconst a = 1\n</script>\n\n<template>\n  <div/>\n</template>\n";
+    assert_eq!(apply("a.vue", script_first), with_header);
+    assert_eq!(apply("a.vue", with_header), with_header);
+    assert_eq!(
+        apply("a.vue", "<template>\n  <div/>\n</template>\n"),
+        "<!-- a.vue -->\n<template>\n  <div/>\n</template>\n"
+    );
+    assert_eq!(
+        apply("a.svelte", "<style>\np {}\n</style>\n"),
+        "<style>\n/* a.svelte */\np {}\n</style>\n"
+    );
+
+    // Moving the script to the top replaces the header in the old style
+    assert_eq!(
+        apply(
+            "a.vue",
+            "<!-- a.vue -->\n<script setup>\nconst a = 1\n</script>\n"
+        ),
+        "<script setup>\n// a.vue\nconst a = 1\n</script>\n"
+    );
+
+    // Astro front matter is TypeScript
+    assert_eq!(
+        apply("a.astro", "---\nconst x = 1\n---\n<h1>hi</h1>\n"),
+        "---\n// a.astro\nconst x = 1\n---\n<h1>hi</h1>\n"
+    );
+
+    // PHP opening with HTML gets an HTML comment, below the doctype
+    assert_eq!(
+        apply("a.php", "<?php\necho 1;\n"),
+        "<?php\n// a.php\necho 1;\n"
+    );
+    assert_eq!(
+        apply("a.php", "<div><?= $a ?></div>\n"),
+        "<!-- a.php -->\n<div><?= $a ?></div>\n"
+    );
+    assert_eq!(
+        apply("a.php", "<!DOCTYPE html>\n<html></html>\n"),
+        "<!DOCTYPE html>\n<!-- a.php -->\n<html></html>\n"
+    );
+
+    // Rules from the config file are added to the defaults, bad ones are skipped
+    let config = Config::parse(concat!(
+        "tpl <!-- -->\n",
+        "resolve tpl before # ^\\{%\n",
+        "resolve tpl sideways # ^x\n",
+        "resolve tpl after nope ^x\n",
+        "resolve tpl after #\n",
+    ));
+    assert_eq!(config.resolvers["tpl"].len(), 1);
+    assert!(config.resolvers.contains_key("vue"));
+    let resolution = config.resolve(Path::new("a.tpl"), "{% block %}\n", Style::Xml);
+    assert_eq!(resolution.style, Style::Hash);
+    assert!(resolution.after.is_none());
+    assert_eq!(resolution.other_styles, vec![Style::Xml]);
+
+    // A style forced with --comment-style skips the resolver
+    let temp_dir = TempDir::new().unwrap();
+    let file = create_test_file(temp_dir.path(), "a.vue", script_first);
+    let mut builder = TestArgsBuilder::new(&temp_dir);
+    builder.args.comment_style = Some("xml".to_string());
+    let (args, temp_path) = builder.build();
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    assert_eq!(cli::Cli::new_arc(args, base_dir, gitignore_path).run(), 0);
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        format!("<!-- a.vue -->\n{script_first}")
+    );
+}
+
+#[test]
+fn test_library_walk_with_default_args() {
+    let temp_dir = TempDir::new().unwrap();
+    let file = create_test_file(temp_dir.path(), "src/lib.rs", "pub fn f() {}\n");
+    let args = Args {
+        paths: vec![temp_dir.path().to_string_lossy().into_owned()],
+        no_git: true,
+        dry_run: true,
+        ..Args::default()
+    };
+    let base = cli::resolve_base_dir(&args).unwrap();
+    assert!(!base.from_git);
+    let cli_arc = cli::Cli::new_arc(args, temp_dir.path().to_path_buf(), base.gitignore_path);
+    let reports = cli_arc.collect_reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].path, Path::new("src/lib.rs"));
+    assert_eq!(reports[0].update().unwrap().header, "// src/lib.rs");
+    assert_eq!("pub fn f() {}\n", fs::read_to_string(&file).unwrap());
+}
+
+#[test]
+fn test_config_warnings_are_collected() {
+    let config = Config::parse("rs //\nfoo\npy nope\npreamble rs line (\nencoding bogus sql\n");
+    assert_eq!(config.style_for(Path::new("a.rs")), Some(Style::Slash));
+    assert_eq!(config.warnings.len(), 4, "{:?}", config.warnings);
+    assert!(config.warnings[0].contains("'(' for extension '.rs'"));
+    assert!(config.warnings.iter().all(|w| !w.starts_with("Warning")));
+    assert!(Config::default().warnings.is_empty());
+
+    // The Cli adds the problems of the project config and filters
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        project::PROJECT_CONFIG_FILENAME,
+        "exclude = [\"[\"]\n\n[styles]\nfoo = \"nope\"\n",
+    );
+    create_test_file(temp_dir.path(), "custom.cfg", "rs //\nfoo\n");
+    let (mut args, temp_path) = TestArgsBuilder::new(&temp_dir)
+        .config_file("custom.cfg")
+        .build();
+    project::apply(&mut args);
+    let (base_dir, gitignore_path) = determine_test_paths(&args, &temp_path);
+    let processor = cli::Cli::new(args, base_dir, gitignore_path);
+    let warnings = processor.warnings();
+    assert_eq!(warnings.len(), 3, "{warnings:?}");
+    assert!(warnings[0].contains("Missing comment style for 'foo'"));
+    assert!(warnings[1].contains("Unknown comment style 'nope'"));
+    assert!(warnings[2].contains("Invalid glob '['"));
+}
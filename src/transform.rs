@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use regex::Regex;
+
+use crate::{comments, placement};
+
+const UTF8_BOM: char = '\u{feff}';
+
+/// Options controlling how an existing header and other path comments are treated
+#[derive(Copy, Clone, Default, Debug)]
+pub struct HeaderOptions<'a> {
+    /// Keep other existing path comments in the file
+    pub keep: bool,
+    /// Remove the path header instead of adding it
+    pub clean: bool,
+    /// Text of the header around the path, the path alone if unset
+    pub template: Option<&'a HeaderTemplate>,
+    /// Put the header right after the first line matching this instead of after the preambles,
+    /// eg inside a `<script>` block
+    pub after: Option<&'a Regex>,
+    /// Path comments in these styles are stripped too, eg the other styles of a
+    /// multi-language file
+    pub other_styles: &'a [comments::Style],
+}
+
+/// Placeholder for the path in a header template
+pub const PATH_PLACEHOLDER: &str = "{path}";
+
+/// Text of the header comment around the path, eg `File: {path}`
+#[derive(Debug)]
+pub struct HeaderTemplate {
+    prefix: String,
+    suffix: String,
+    /// Matches headers following the template with any path, built per comment style on first use
+    regexes: Mutex<HashMap<comments::Style, Arc<Regex>>>,
+}
+
+impl HeaderTemplate {
+    /// Parses a template, which must contain the `{path}` placeholder once
+    pub fn parse(template: &str) -> Option<Self> {
+        let (prefix, suffix) = template.split_once(PATH_PLACEHOLDER)?;
+        if suffix.contains(PATH_PLACEHOLDER) {
+            return None;
+        }
+        Some(Self {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            regexes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The regex matching headers that follow the template in `style`, with any path
+    fn regex(&self, style: comments::Style) -> Arc<Regex> {
+        let mut regexes = self.regexes.lock().unwrap();
+        let regex = regexes.entry(style).or_insert_with(|| {
+            let (start, end) = style.delimiters();
+            let pattern = format!(
+                r"^{}\s*{}(?:{}){}\s*{}$",
+                regex::escape(start),
+                regex::escape(&self.prefix),
+                comments::PATH_PATTERN,
+                regex::escape(&self.suffix),
+                regex::escape(end)
+            );
+            Arc::new(Regex::new(&pattern).expect("escaped template is a valid regex"))
+        });
+        Arc::clone(regex)
+    }
+
+    /// The header text for `rel_path`, without the comment delimiters
+    pub fn render(&self, rel_path: &str) -> String {
+        format!("{}{rel_path}{}", self.prefix, self.suffix)
+    }
+}
+
+/// What applying the header does to a file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HeaderAction {
+    /// The header is added, no other path comment was found
+    Added,
+    /// The header replaces (or is added next to removed) stale path comments
+    Updated,
+    /// Path comments are removed with `clean`
+    Removed,
+    /// The file already had the correct header
+    Unchanged,
+}
+
+impl HeaderAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HeaderAction::Added => "added",
+            HeaderAction::Updated => "updated",
+            HeaderAction::Removed => "removed",
+            HeaderAction::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// The result of applying a path header to some file content
+#[derive(Clone, Debug)]
+pub struct HeaderUpdate {
+    /// The header line for the file, eg `// src/main.rs`
+    pub header: String,
+    /// Whether the file already had the correct header in the right place
+    pub had_header: bool,
+    /// Other path comments that were stripped from the file
+    pub stripped: Vec<String>,
+    /// The new file content
+    pub content: String,
+    /// Whether `content` differs from the original
+    pub changed: bool,
+    pub action: HeaderAction,
+}
+
+/// Formats a path relative to `base_dir` the way it appears in the header: forward slashes,
+/// no leading `./`.
+pub fn header_path(path: &Path, base_dir: &Path) -> String {
+    let rel_path = match path.strip_prefix(base_dir) {
+        Ok(rel) => rel.to_path_buf(),
+        // Paths given relative to the working directory need to be made absolute first
+        Err(_) => match path.canonicalize() {
+            Ok(abs) if abs.starts_with(base_dir) => {
+                abs.strip_prefix(base_dir).unwrap_or(&abs).to_path_buf()
+            }
+            // If stripping fails (e.g., path is not under base_dir), use the full path.
+            // This might happen if base_dir logic changes or symlinks are involved.
+            _ => path.to_path_buf(),
+        },
+    };
+    // Convert to string, ensuring forward slashes for consistency
+    let rel_path_str = rel_path.to_string_lossy().replace('\\', "/");
+    rel_path_str.trim_start_matches("./").to_string()
+}
+
+/// Formats the change from `original` to `updated` as a unified diff for the file at `rel_path`,
+/// with `a/` and `b/` prefixes so it can be applied with `git apply` from the base directory.
+pub fn unified_diff(original: &str, updated: &str, rel_path: &str) -> String {
+    similar::TextDiff::from_lines(original, updated)
+        .unified_diff()
+        .header(&format!("a/{rel_path}"), &format!("b/{rel_path}"))
+        .to_string()
+}
+
+/// Applies the path header for `rel_path` to `content` without touching the disk.
+pub fn apply_header(
+    content: &str,
+    rel_path: &str,
+    style: comments::Style,
+    preambles: &[&placement::Preamble],
+    options: HeaderOptions,
+) -> HeaderUpdate {
+    // Build the new header comment
+    let (comment_start, comment_end) = style.delimiters();
+    let text = match options.template {
+        Some(template) => template.render(rel_path),
+        None => rel_path.to_string(),
+    };
+    let first_line = format!("{comment_start}{text}{comment_end}");
+
+    // A UTF-8 byte order mark must stay at the very start of the file
+    let (bom, body) = match content.strip_prefix(UTF8_BOM) {
+        Some(body) => (UTF8_BOM.to_string(), body),
+        None => (String::new(), content),
+    };
+
+    // Split the content into lines for easier manipulation, keeping each line's own ending so
+    // untouched lines come out byte-for-byte identical
+    let (lines, endings): (Vec<&str>, Vec<&str>) = split_lines(body).into_iter().unzip();
+    let eol = dominant_line_ending(&endings);
+
+    // Plain path comments are recognized too, so adopting a template replaces them
+    let path_comment_re = style.regex();
+    let template_re = options.template.map(|template| template.regex(style));
+    let is_path_comment = |line: &str| {
+        // Use trim() to ignore leading/trailing whitespace when matching
+        let line = line.trim();
+        path_comment_re.is_match(line)
+            || template_re.as_ref().is_some_and(|re| re.is_match(line))
+            || options
+                .other_styles
+                .iter()
+                .any(|style| style.regex().is_match(line))
+    };
+
+    // Path comments above a preamble were put there by an older version and break it (a
+    // shebang or XML declaration must come first). They are stale whatever the options.
+    let misplaced = match options.after {
+        Some(_) => 0,
+        None => {
+            let leading = lines
+                .iter()
+                .take_while(|line| is_path_comment(line))
+                .count();
+            if leading > 0 && placement::header_insert_index(&lines[leading..], preambles) > 0 {
+                leading
+            } else {
+                0
+            }
+        }
+    };
+
+    // The header goes after any preamble (shebang, XML declaration, front matter, ...), unless
+    // it belongs after a specific line
+    let insert_at = options
+        .after
+        .and_then(|re| lines.iter().position(|line| re.is_match(line.trim())))
+        .map_or_else(
+            || misplaced + placement::header_insert_index(&lines[misplaced..], preambles),
+            |index| index + 1,
+        );
+
+    // First, check if the header line is exactly our desired comment
+    let already_had_path_comment = lines
+        .get(insert_at)
+        .is_some_and(|line| line.trim() == first_line.trim());
+
+    // If the correct comment is already there AND we are not stripping other potential
+    // path comments, we can skip modification entirely.
+    if already_had_path_comment && misplaced == 0 && (options.keep || !options.clean) {
+        return HeaderUpdate {
+            header: first_line,
+            had_header: true,
+            stripped: Vec::new(),
+            content: content.to_string(),
+            changed: false,
+            action: HeaderAction::Unchanged,
+        };
+    }
+
+    // Find all existing path-looking comments *if* stripping is enabled
+    let mut path_comment_line_numbers: Vec<usize> = (0..misplaced).collect();
+    if !options.keep {
+        for (line_num, line) in lines.iter().enumerate().skip(misplaced) {
+            if line_num == insert_at && already_had_path_comment {
+                continue;
+            }
+            if is_path_comment(line) {
+                path_comment_line_numbers.push(line_num);
+            }
+        }
+    }
+
+    // Build the final (line, ending) pairs, placing the header at `insert_at`
+    let mut final_content_lines: Vec<(&str, &str)> = Vec::with_capacity(lines.len() + 1);
+
+    for (i, (line, ending)) in lines.iter().zip(&endings).enumerate() {
+        if i == insert_at {
+            if !options.clean {
+                final_content_lines.push((first_line.as_str(), eol));
+            }
+            // The original header line was already correct, it has been replaced above
+            if already_had_path_comment {
+                continue;
+            }
+        }
+
+        // Add the line if it's not a path comment we're stripping
+        if !path_comment_line_numbers.contains(&i) {
+            final_content_lines.push((line, ending));
+        }
+    }
+
+    // The header goes at the very end when the file is nothing but preambles (or empty)
+    if insert_at >= lines.len() && !options.clean {
+        final_content_lines.push((first_line.as_str(), eol));
+    }
+
+    // Join the lines back together. Only the original last line can lack an ending, and it
+    // needs one if something now comes after it.
+    let mut new_content = bom;
+    let last = final_content_lines.len().saturating_sub(1);
+    for (i, (line, ending)) in final_content_lines.iter().enumerate() {
+        new_content.push_str(line);
+        if i < last || !ending.is_empty() {
+            new_content.push_str(if ending.is_empty() { eol } else { ending });
+        }
+    }
+
+    // Preserve trailing line ending if original had one or was empty
+    let had_trailing_ending = endings.last().is_some_and(|ending| !ending.is_empty());
+    if had_trailing_ending || body.is_empty() {
+        // Ensure only one trailing line ending
+        if !new_content.ends_with(['\n', '\r']) {
+            new_content.push_str(eol);
+        }
+    } else if let Some((_, ending)) = final_content_lines.last() {
+        // Original didn't end with a line ending, ensure new one doesn't either
+        new_content.truncate(new_content.len() - ending.len());
+    }
+
+    let stripped: Vec<String> = path_comment_line_numbers
+        .iter()
+        .map(|&line_num| lines[line_num].to_string())
+        .collect();
+
+    let changed = new_content != content;
+    let action = if !changed {
+        HeaderAction::Unchanged
+    } else if options.clean {
+        HeaderAction::Removed
+    } else if already_had_path_comment || !stripped.is_empty() {
+        HeaderAction::Updated
+    } else {
+        HeaderAction::Added
+    };
+
+    HeaderUpdate {
+        changed,
+        action,
+        header: first_line,
+        had_header: already_had_path_comment,
+        stripped,
+        content: new_content,
+    }
+}
+
+/// Whether `content` opens with a module docstring (the first statement after the preambles,
+/// blank lines and `#` comments is a string) other than a path one. A `docstring` style header
+/// would take its place as the module's `__doc__`.
+pub fn has_docstring(content: &str, preambles: &[&placement::Preamble]) -> bool {
+    let body = content.strip_prefix(UTF8_BOM).unwrap_or(content);
+    let lines: Vec<&str> = split_lines(body)
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect();
+    let start = placement::header_insert_index(&lines, preambles);
+    let Some(statement) = lines
+        .iter()
+        .skip(start)
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+    else {
+        return false;
+    };
+    // String prefixes like `r"""` or `u'`
+    let literal = statement.trim_start_matches(['r', 'R', 'u', 'U', 'b', 'B', 'f', 'F']);
+    literal.starts_with(['"', '\'']) && !comments::Style::Docstring.regex().is_match(statement)
+}
+
+/// Splits `text` into lines, each paired with the ending that terminated it (`\n`, `\r\n` or a
+/// lone `\r`). The last line has an empty ending if the text doesn't end with a line break.
+fn split_lines(text: &str) -> Vec<(&str, &str)> {
+    let bytes = text.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let end = match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => i + 2,
+            b'\r' | b'\n' => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        lines.push((&text[start..i], &text[i..end]));
+        start = end;
+        i = end;
+    }
+    if start < text.len() {
+        lines.push((&text[start..], ""));
+    }
+    lines
+}
+
+/// The most common line ending among `endings`, used for the lines that are added.
+/// Ties go to `\n`, then `\r\n`.
+fn dominant_line_ending(endings: &[&str]) -> &'static str {
+    ["\n", "\r\n", "\r"]
+        .into_iter()
+        .rev()
+        .max_by_key(|eol| endings.iter().filter(|ending| *ending == eol).count())
+        .unwrap_or("\n")
+}
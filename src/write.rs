@@ -0,0 +1,49 @@
+use std::{
+    fs::{self, FileTimes},
+    io::{self, Write},
+    path::Path,
+};
+
+use tempfile::NamedTempFile;
+
+/// Replaces the content of the file at `path` without ever leaving it truncated: the content is
+/// written to a temporary file in the same directory, which is then renamed over the original.
+///
+/// The permission bits (and ownership, where allowed) of the original are kept, and its
+/// modification time too if `preserve_mtime` is set. Symlinks are followed, so the file they point
+/// to is updated instead of the link being replaced. Read-only files are refused, as replacing them
+/// by renaming would go around their permissions.
+pub fn write_atomic(
+    path: &Path,
+    content: impl AsRef<[u8]>,
+    preserve_mtime: bool,
+) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&path)?;
+    if metadata.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "file is read-only",
+        ));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(content.as_ref())?;
+
+    let file = temp.as_file();
+    file.set_permissions(metadata.permissions())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Only possible for root or when the owner is unchanged, so failing is fine
+        let _ = std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    if preserve_mtime {
+        file.set_times(FileTimes::new().set_modified(metadata.modified()?))?;
+    }
+    file.sync_all()?;
+
+    temp.persist(&path).map_err(|e| e.error)?;
+    Ok(())
+}